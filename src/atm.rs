@@ -1,94 +1,3104 @@
-use anyhow::Result;
-use csv::{ReaderBuilder, Trim, Writer};
+use anyhow::{anyhow, bail, Context, Result};
+use csv::{ReaderBuilder, Trim, Writer, WriterBuilder};
 use rust_decimal::Decimal;
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io;
 use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::client::Client;
+use crate::client::{Client, ClientOutput, ClientRecord, IgnoreReason};
+use crate::config::{Config, EofDisputePolicy, OutputOptions};
+use crate::invariant::{Violation, ViolationKind};
+use crate::ledger::{Balances, LedgerEvent};
+use crate::reader::{Row, TransactionReader};
+use crate::reconcile::Discrepancy;
+use crate::report::ProcessingReport;
+use crate::sha256::HashingWriter;
+use crate::source::{CsvSource, SkippedRow, TransactionSource};
+use crate::state::{RecordKind, StateRow};
+use crate::validate::{Diagnostic, ValidationReport};
 
 /// The different types of actions a client can take.
 ///
 /// Encode the transaction amount in deposit/withdrawal,
 /// since it doesn't exist for the other variants.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionVariant {
-    Deposit { amount: Decimal },
-    Withdrawal { amount: Decimal },
+    Deposit {
+        amount: Decimal,
+    },
+    Withdrawal {
+        amount: Decimal,
+    },
     Dispute,
     Resolve,
     Chargeback,
+    /// Reverse a deposit or withdrawal entered in error.
+    Correction,
+    /// Unlock a client locked by a chargeback. The tx isn't used.
+    Unlock,
+    /// A fee, when negative, or a credit like interest, when positive. Can't be disputed.
+    Adjustment {
+        amount: Decimal,
+    },
+}
+
+impl TransactionVariant {
+    /// The name used in the `type` column.
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionVariant::Deposit { .. } => "deposit",
+            TransactionVariant::Withdrawal { .. } => "withdrawal",
+            TransactionVariant::Dispute => "dispute",
+            TransactionVariant::Resolve => "resolve",
+            TransactionVariant::Chargeback => "chargeback",
+            TransactionVariant::Correction => "correction",
+            TransactionVariant::Unlock => "unlock",
+            TransactionVariant::Adjustment { .. } => "adjustment",
+        }
+    }
+
+    /// The amount of a deposit, withdrawal or adjustment.
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            TransactionVariant::Deposit { amount }
+            | TransactionVariant::Withdrawal { amount }
+            | TransactionVariant::Adjustment { amount } => Some(*amount),
+            _ => None,
+        }
+    }
+
+    /// If the variant carries an amount.
+    pub fn has_amount(&self) -> bool {
+        self.amount().is_some()
+    }
 }
 
 /// A transaction describing an action a client can take.
-#[derive(Debug, Deserialize)]
+///
+/// Serialized as the columns `type,client,tx,amount` with an empty amount for
+/// variants without one, matching the input format. Use `with_currency` for inputs
+/// with a `currency` column.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Transaction {
-    pub client: u16,
+    pub client: u32,
     pub tx: u32,
     // Use internally tagged unions and struct flattening to encode common fields
     // next to type specific fields.
     // See: https://stackoverflow.com/questions/61205057/serde-internally-tagged-enum-with-common-fields
     #[serde(flatten)]
     pub variant: TransactionVariant,
+    /// The currency of the client, disputes and other control rows inherit the
+    /// currency of their tx.
+    #[serde(default)]
+    pub currency: Option<String>,
 }
 
-/// An atm holding the state of the payment processor.
+// Serialize manually since csv doesn't support the map serde uses for flattened fields.
+impl Serialize for Transaction {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut row = serializer.serialize_struct("Transaction", 4)?;
+        row.serialize_field("type", self.variant.name())?;
+        row.serialize_field("client", &self.client)?;
+        row.serialize_field("tx", &self.tx)?;
+        row.serialize_field("amount", &self.variant.amount())?;
+        row.end()
+    }
+}
+
+impl Transaction {
+    /// Serialize with a trailing `currency` column, empty for transactions without one.
+    pub fn with_currency(&self) -> WithCurrency<'_> {
+        WithCurrency(self)
+    }
+}
+
+/// A transaction serialized as the columns `type,client,tx,amount,currency`.
+#[derive(Debug, Clone, Copy)]
+pub struct WithCurrency<'a>(&'a Transaction);
+
+impl Serialize for WithCurrency<'_> {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let transaction = self.0;
+        let mut row = serializer.serialize_struct("Transaction", 5)?;
+        row.serialize_field("type", transaction.variant.name())?;
+        row.serialize_field("client", &transaction.client)?;
+        row.serialize_field("tx", &transaction.tx)?;
+        row.serialize_field("amount", &transaction.variant.amount())?;
+        row.serialize_field("currency", &transaction.currency)?;
+        row.end()
+    }
+}
+
+/// A client being locked by a transaction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockEvent {
+    pub client: u32,
+    /// The transaction that triggered the lock.
+    pub tx: u32,
+    /// The state of the client after the lock.
+    pub snapshot: ClientOutput,
+}
+
+/// Why a transaction in a batch wasn't applied.
 #[derive(Debug)]
+pub enum AtmError {
+    /// The transaction was ignored, like a withdrawal without enough funds.
+    Ignored(IgnoreReason),
+    /// Applying the transaction failed a sanity check.
+    Failed(anyhow::Error),
+}
+
+impl fmt::Display for AtmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtmError::Ignored(reason) => write!(f, "Ignored: {}", reason),
+            AtmError::Failed(err) => write!(f, "Failed: {}", err),
+        }
+    }
+}
+
+impl Error for AtmError {}
+
+/// The state of a client at a point in time.
+pub type ClientSnapshot = ClientOutput;
+
+/// How often the deadline is checked when processing with a deadline.
+pub const DEADLINE_CHECK_ROWS: usize = 256;
+
+/// An atm holding the state of the payment processor.
+#[derive(Debug, Default)]
 pub struct Atm {
-    pub clients: HashMap<u16, Client>,
+    clients: HashMap<u32, Client>,
+    config: Config,
+    report: ProcessingReport,
+    /// The ids of all deposits and withdrawals, used to skip replayed transactions.
+    seen_txs: HashSet<u32>,
+    /// The latest reason each tx was ignored, if enabled.
+    ignored: HashMap<(u32, u32), IgnoreReason>,
+    /// Every applied and ignored transaction in order, if enabled.
+    events: Vec<LedgerEvent>,
 }
 
 impl Atm {
     pub fn new() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(config: Config) -> Self {
         Self {
             clients: HashMap::new(),
+            config,
+            report: ProcessingReport::default(),
+            seen_txs: HashSet::new(),
+            ignored: HashMap::new(),
+            events: Vec::new(),
         }
     }
 
     /// Create a new atm and process transactions from the csv file specifeid by 'path'.
     pub fn from_path(path: &Path) -> Result<Self> {
         let mut atm = Atm::new();
-        let mut reader = ReaderBuilder::new().trim(Trim::All).from_path(path)?;
-        for row in reader.deserialize() {
-            let transaction: Transaction = row?;
-            atm.execute(transaction)?;
-        }
+        atm.process_path(path)?;
+        atm.finish()?;
         Ok(atm)
     }
 
-    fn execute(&mut self, t: Transaction) -> Result<()> {
-        let client = self.get_or_create_client(t.client);
-        client.execute(t)
+    /// Create a new atm and process transactions from the csv file specified by 'path',
+    /// also returning the ids of locked clients sorted by id.
+    pub fn from_path_with_locks(path: &Path) -> Result<(Self, Vec<u32>)> {
+        let atm = Atm::from_path(path)?;
+        let mut locked: Vec<u32> = atm
+            .clients
+            .values()
+            .filter(|client| client.locked())
+            .map(Client::id)
+            .collect();
+        locked.sort_unstable();
+        Ok((atm, locked))
+    }
+
+    /// Create a new atm and process transactions in a csv format from 'reader' until
+    /// 'deadline' has elapsed, also returning if processing stopped before the end.
+    ///
+    /// The deadline is checked every `DEADLINE_CHECK_ROWS` rows.
+    pub fn from_reader_with_deadline<R: io::Read>(
+        reader: R,
+        deadline: Duration,
+    ) -> Result<(Self, bool)> {
+        let start = Instant::now();
+        let mut atm = Atm::new();
+        let mut reader = TransactionReader::new(reader, &atm.config)?;
+        let mut rows = 0;
+        while let Some((line, row)) = reader.next_row()? {
+            if rows % DEADLINE_CHECK_ROWS == 0 && start.elapsed() >= deadline {
+                return Ok((atm, true));
+            }
+            rows += 1;
+            atm.process_row(line, row)?;
+        }
+        atm.finish()?;
+        Ok((atm, false))
+    }
+
+    /// Process transactions from the csv file specified by 'path'.
+    pub fn process_path(&mut self, path: &Path) -> Result<()> {
+        let file = File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        self.process_reader(file)
+    }
+
+    /// Process transactions in a csv format from 'reader'.
+    ///
+    /// With more than one of the configured `workers`, clients are processed in parallel
+    /// as by `process_reader_parallel`.
+    pub fn process_reader<R: io::Read>(&mut self, reader: R) -> Result<()> {
+        if self.config.workers > 1 {
+            return self.process_reader_parallel(reader, self.config.workers);
+        }
+        self.process_reader_with_locks(reader, |_| {})
+    }
+
+    /// Process transactions in a csv format from 'reader', calling 'on_lock' whenever
+    /// a transaction locks a client.
+    pub fn process_reader_with_locks<R, F>(&mut self, reader: R, on_lock: F) -> Result<()>
+    where
+        R: io::Read,
+        F: FnMut(LockEvent),
+    {
+        let source = CsvSource::new(reader, &self.config)?;
+        self.process_source_with_locks(source, on_lock)
+    }
+
+    /// Process transactions from 'source'.
+    pub fn process_source<S: TransactionSource>(&mut self, source: S) -> Result<()> {
+        self.process_source_with_locks(source, |_| {})
+    }
+
+    /// Process transactions from 'source', calling 'on_lock' whenever a transaction
+    /// locks a client.
+    pub fn process_source_with_locks<S, F>(&mut self, mut source: S, mut on_lock: F) -> Result<()>
+    where
+        S: TransactionSource,
+        F: FnMut(LockEvent),
+    {
+        while let Some(transaction) = source.next_transaction() {
+            let event = match transaction {
+                Ok(transaction) => self.execute(transaction)?,
+                Err(err) => match err.downcast::<SkippedRow>() {
+                    Ok(skipped) => self.process_row(skipped.line, Row::Skipped(skipped.reason))?,
+                    Err(err) => return Err(err),
+                },
+            };
+            if let Some(event) = event {
+                on_lock(event);
+            }
+        }
+        Ok(())
     }
 
-    fn get_or_create_client(&mut self, client: u16) -> &mut Client {
+    /// Process transactions in a csv format from 'reader', reading on a separate thread.
+    ///
+    /// Rows are passed in order through a channel bounded by the configured
+    /// `channel_capacity`, and reading blocks while the channel is full.
+    pub fn process_buffered<R: io::Read + Send>(&mut self, reader: R) -> Result<()> {
+        let mut reader = TransactionReader::new(reader, &self.config)?;
+        let (sender, receiver) = mpsc::sync_channel(self.config.channel_capacity);
+        thread::scope(|scope| {
+            scope.spawn(move || loop {
+                let row = reader.next_row();
+                let done = !matches!(row, Ok(Some(_)));
+                // The receiver hangs up if processing fails.
+                if sender.send(row).is_err() || done {
+                    break;
+                }
+            });
+            for row in receiver {
+                match row? {
+                    Some((line, row)) => {
+                        self.process_row(line, row)?;
+                    }
+                    None => break,
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        })?;
+        Ok(())
+    }
+
+    /// Process transactions in a csv format from 'reader' with clients sharded over
+    /// 'workers' threads.
+    ///
+    /// The input is read up front and every client is processed by a single worker,
+    /// so transactions keep their order within a client, like a dispute following
+    /// its deposit. The result is the same as processing the input sequentially.
+    pub fn process_reader_parallel<R: io::Read>(
+        &mut self,
+        reader: R,
+        workers: usize,
+    ) -> Result<()> {
+        let workers = workers.max(1);
+        // Transactions are numbered in input order to merge the event logs of the workers.
+        let mut shards: Vec<Vec<(usize, Transaction, bool)>> =
+            (0..workers).map(|_| Vec::new()).collect();
+        let mut reader = TransactionReader::new(reader, &self.config)?;
+        let mut index = 0;
+        while let Some((line, row)) = reader.next_row()? {
+            match row {
+                Row::Transaction(t) => {
+                    // Tx ids are unique across clients, so duplicates are found before sharding.
+                    let duplicate = t.variant.has_amount() && !self.seen_txs.insert(t.tx);
+                    shards[t.client as usize % workers].push((index, t, duplicate));
+                    index += 1;
+                }
+                Row::Skipped(_) => {
+                    self.process_row(line, row)?;
+                }
+            }
+        }
+
+        let mut atms: Vec<Atm> = (0..workers)
+            .map(|_| Atm::with_config(self.config.clone()))
+            .collect();
+        for (id, client) in self.clients.drain() {
+            atms[id as usize % workers].clients.insert(id, client);
+        }
+        let results: Vec<Result<Vec<usize>>> = thread::scope(|scope| {
+            let handles: Vec<_> = atms
+                .iter_mut()
+                .zip(shards)
+                .map(|(atm, shard)| {
+                    scope.spawn(move || {
+                        let mut order = Vec::new();
+                        for (index, t, duplicate) in shard {
+                            order.push(index);
+                            if duplicate {
+                                let (client, tx) = (t.client, t.tx);
+                                atm.log_skipped(Some(t), IgnoreReason::DuplicateTx);
+                                atm.ignore(client, tx, IgnoreReason::DuplicateTx)?;
+                            } else {
+                                atm.execute(t)?;
+                            }
+                        }
+                        Ok(order)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("worker panicked"))
+                .collect()
+        });
+        let mut events = Vec::new();
+        for (atm, order) in atms.into_iter().zip(&results) {
+            self.clients.extend(atm.clients);
+            self.ignored.extend(atm.ignored);
+            self.report.processed += atm.report.processed;
+            self.report.skipped += atm.report.skipped;
+            self.report.rejected_due_to_lock += atm.report.rejected_due_to_lock;
+            if let Ok(order) = order {
+                events.extend(order.iter().copied().zip(atm.events));
+            }
+        }
+        events.sort_by_key(|(index, _)| *index);
+        self.events
+            .extend(events.into_iter().map(|(_, event)| event));
+        results.into_iter().collect::<Result<Vec<_>>>()?;
+        Ok(())
+    }
+
+    /// Process transactions grouped by client from 'reader', writing the state of each client
+    /// in a csv format to 'writer' as soon as the rows of the next client start.
+    ///
+    /// Written clients are removed from the atm, so memory is only held for the current client.
+    /// 'reader' is taken as the whole input, so each client is settled as by `finish` before
+    /// it's written. Fails if the rows of a client aren't contiguous.
+    pub fn stream_sorted<R, W>(
+        &mut self,
+        reader: R,
+        writer: W,
+        options: &OutputOptions,
+    ) -> Result<()>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        if !options.checksum {
+            let mut writer = csv_writer(writer, options)?;
+            self.stream_clients(reader, &mut writer, options)?;
+            return Ok(());
+        }
+        let mut writer = csv_writer(HashingWriter::new(writer), options)?;
+        let clients = self.stream_clients(reader, &mut writer, options)?;
+        write_checksum(writer, clients)
+    }
+
+    /// Process and write clients for `stream_sorted`, returning the number of clients written.
+    fn stream_clients<R, W>(
+        &mut self,
+        reader: R,
+        writer: &mut Writer<W>,
+        options: &OutputOptions,
+    ) -> Result<usize>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        let mut reader = TransactionReader::new(reader, &self.config)?;
+        let mut current = None;
+        let mut written = HashSet::new();
+        let mut clients = 0;
+        let mut unresolved = Vec::new();
+        while let Some((line, row)) = reader.next_row()? {
+            let transaction = match row {
+                Row::Transaction(transaction) => transaction,
+                Row::Skipped(_) => {
+                    self.process_row(line, row)?;
+                    continue;
+                }
+            };
+            if current != Some(transaction.client) {
+                if let Some(id) = current {
+                    if self.write_client(id, writer, options, &mut unresolved)? {
+                        clients += 1;
+                    }
+                    written.insert(id);
+                }
+                if written.contains(&transaction.client) {
+                    bail!(
+                        "Transactions for client {} aren't contiguous",
+                        transaction.client
+                    );
+                }
+                current = Some(transaction.client);
+            }
+            self.execute(transaction)?;
+        }
+        if let Some(id) = current {
+            if self.write_client(id, writer, options, &mut unresolved)? {
+                clients += 1;
+            }
+        }
+        // Written clients are gone by now, so their disputes were collected as they were written.
+        if self.config.report_unresolved_disputes {
+            self.report.unresolved_disputes = unresolved;
+        }
+        Ok(clients)
+    }
+
+    /// Settle the open disputes of a client, then remove it and write its state to 'writer'.
+    ///
+    /// The disputes left open are added to 'unresolved' as `(client, tx)` sorted by tx id.
+    /// Returns if a row was written.
+    fn write_client<W: io::Write>(
+        &mut self,
+        id: u32,
+        writer: &mut Writer<W>,
+        options: &OutputOptions,
+        unresolved: &mut Vec<(u32, u32)>,
+    ) -> Result<bool> {
+        self.settle_disputes(id)?;
+        let client = match self.clients.remove(&id) {
+            Some(client) => client,
+            None => return Ok(false),
+        };
+        let written = write_row(writer, &client, options)?;
+        writer.flush()?;
+        unresolved.extend(client.disputed_txs().into_iter().map(|tx| (id, tx)));
+        Ok(written)
+    }
+
+    /// Tallies of the transactions processed so far.
+    pub fn report(&self) -> &ProcessingReport {
+        &self.report
+    }
+
+    /// Iterate over the state of all clients, in no particular order.
+    pub fn clients_iter(&self) -> impl Iterator<Item = &Client> {
+        self.clients.values()
+    }
+
+    /// The `(client, available, held, total, locked)` balances of all clients, sorted by client id.
+    pub fn balances(&self) -> Vec<(u32, Decimal, Decimal, Decimal, bool)> {
+        let mut balances: Vec<_> = self
+            .clients
+            .values()
+            .map(|c| (c.id(), c.available(), c.held(), c.total(), c.locked()))
+            .collect();
+        balances.sort_by_key(|balance| balance.0);
+        balances
+    }
+
+    /// The state of all clients with amounts in minor units, sorted by client id.
+    pub fn records(&self) -> Vec<ClientRecord> {
+        let mut records: Vec<ClientRecord> = self
+            .clients
+            .values()
+            .map(|client| ClientRecord::new(client, self.config.precision))
+            .collect();
+        records.sort_by_key(|record| record.client);
+        records
+    }
+
+    /// Fill 'buf' with the records of clients sorted by client id, returning the number
+    /// of records written.
+    ///
+    /// If 'buf' is too small only the records of the clients with the lowest ids are written.
+    pub fn fill_records(&self, buf: &mut [ClientRecord]) -> usize {
+        let records = self.records();
+        let written = records.len().min(buf.len());
+        buf[..written].copy_from_slice(&records[..written]);
+        written
+    }
+
+    fn process_row(&mut self, line: u64, row: Row) -> Result<Option<LockEvent>> {
+        match row {
+            Row::Transaction(transaction) => self.execute(transaction),
+            Row::Skipped(reason) => {
+                if self.config.fail_fast {
+                    bail!("Skipped line {}: {}", line, reason);
+                }
+                self.report.skipped += 1;
+                if self.config.verbose {
+                    eprintln!("Skipped line {}: {}", line, reason);
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Settle open disputes and update the report at the end of the whole input.
+    ///
+    /// Call once after processing the last input, as inputs processed one after the other
+    /// are equivalent to their concatenation. Constructors like `from_path` call it already.
+    pub fn finish(&mut self) -> Result<()> {
+        let mut ids: Vec<u32> = self.clients.keys().copied().collect();
+        ids.sort_unstable();
+        for id in ids {
+            self.settle_disputes(id)?;
+        }
+        if self.config.report_unresolved_disputes {
+            self.report.unresolved_disputes = self.open_disputes();
+        }
+        Ok(())
+    }
+
+    /// Apply the `eof_disputes` policy to the open disputes of 'client'.
+    fn settle_disputes(&mut self, client: u32) -> Result<()> {
+        let variant = match self.config.eof_disputes {
+            EofDisputePolicy::Keep => return Ok(()),
+            EofDisputePolicy::Resolve => TransactionVariant::Resolve,
+            EofDisputePolicy::Chargeback => TransactionVariant::Chargeback,
+        };
+        let txs = match self.clients.get(&client) {
+            Some(client) => client.disputed_txs(),
+            None => return Ok(()),
+        };
+        for tx in txs {
+            self.execute(Transaction {
+                client,
+                tx,
+                variant: variant.clone(),
+                currency: None,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The client and tx ids of all disputed txs, sorted by ids.
+    fn open_disputes(&self) -> Vec<(u32, u32)> {
+        let mut disputes: Vec<(u32, u32)> = self
+            .clients
+            .values()
+            .flat_map(|client| {
+                client
+                    .disputed_txs()
+                    .into_iter()
+                    .map(move |tx| (client.id(), tx))
+            })
+            .collect();
+        disputes.sort_unstable();
+        disputes
+    }
+
+    /// The reason the latest transaction referencing 'tx' of 'client' was ignored.
+    ///
+    /// Only available with the `record_ignored` option, and `None` if it was applied.
+    pub fn why_ignored(&self, client: u32, tx: u32) -> Option<IgnoreReason> {
+        self.ignored.get(&(client, tx)).copied()
+    }
+
+    /// The applied and ignored transactions in the order they were processed.
+    ///
+    /// Only available with the `event_log` option.
+    pub fn events(&self) -> &[LedgerEvent] {
+        &self.events
+    }
+
+    /// The sum of held funds over all clients, or `None` if it overflows.
+    pub fn total_held(&self) -> Option<Decimal> {
         self.clients
-            .entry(client)
-            .or_insert_with(|| Client::new(client))
+            .values()
+            .try_fold(Decimal::ZERO, |sum, client| sum.checked_add(client.held()))
+    }
+
+    /// The ids of clients only referenced by control transactions, sorted by id.
+    ///
+    /// A client without any deposits or withdrawals is most likely a data error.
+    pub fn control_only_clients(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .clients
+            .values()
+            .filter(|client| client.control_only())
+            .map(Client::id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Execute a transaction, returning a lock event if it locked the client.
+    pub(crate) fn execute(&mut self, t: Transaction) -> Result<Option<LockEvent>> {
+        let (id, tx) = (t.client, t.tx);
+        match self.try_execute(t)? {
+            Ok(event) => Ok(event),
+            Err(reason) => {
+                self.ignore(id, tx, reason)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Apply a transaction and log it, leaving ignored transactions to the caller.
+    fn try_execute(
+        &mut self,
+        t: Transaction,
+    ) -> Result<std::result::Result<Option<LockEvent>, IgnoreReason>> {
+        let (id, tx) = (t.client, t.tx);
+        let logged = if self.config.event_log {
+            Some(t.clone())
+        } else {
+            None
+        };
+        let before = self
+            .clients
+            .get(&id)
+            .map(Balances::from)
+            .unwrap_or_default();
+        let applied = self.apply(t)?;
+        match &applied {
+            Ok(event) => {
+                self.report.processed += 1;
+                if self.config.record_ignored {
+                    self.ignored.remove(&(id, tx));
+                }
+                if let Some(transaction) = logged {
+                    self.events.push(LedgerEvent::Applied {
+                        transaction,
+                        before,
+                        after: Balances::from(&self.clients[&id]),
+                        locked: event.is_some(),
+                    });
+                }
+            }
+            Err(reason) => self.log_skipped(logged, *reason),
+        }
+        Ok(applied)
+    }
+
+    /// Apply 'txs' in order, returning the index and error of every transaction that
+    /// was ignored or failed.
+    ///
+    /// Unlike processing a file, a failing transaction doesn't stop the batch, and the
+    /// `fail_fast` option doesn't apply.
+    pub fn apply_many<I>(&mut self, txs: I) -> Vec<(usize, AtmError)>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        let mut errors = Vec::new();
+        for (i, t) in txs.into_iter().enumerate() {
+            let (id, tx) = (t.client, t.tx);
+            match self.try_execute(t) {
+                Ok(Ok(_)) => {}
+                Ok(Err(reason)) => {
+                    self.tally_ignored(id, tx, reason);
+                    errors.push((i, AtmError::Ignored(reason)));
+                }
+                Err(err) => errors.push((i, AtmError::Failed(err))),
+            }
+        }
+        errors
+    }
+
+    fn log_skipped(&mut self, transaction: Option<Transaction>, reason: IgnoreReason) {
+        if let (true, Some(transaction)) = (self.config.event_log, transaction) {
+            self.events.push(LedgerEvent::Skipped {
+                transaction,
+                reason,
+            });
+        }
+    }
+
+    /// Apply a transaction without updating the report.
+    fn apply(
+        &mut self,
+        t: Transaction,
+    ) -> Result<std::result::Result<Option<LockEvent>, IgnoreReason>> {
+        let tx = t.tx;
+        let id = t.client;
+        // Deposits and withdrawals introduce new tx ids, which are globally unique.
+        if t.variant.has_amount() && !self.seen_txs.insert(tx) {
+            return Ok(Err(IgnoreReason::DuplicateTx));
+        }
+
+        let client = self.clients.entry(id).or_insert_with(|| Client::new(id));
+        let was_locked = client.locked();
+        if let Some(reason) = client.execute(t, &self.config)? {
+            return Ok(Err(reason));
+        }
+        let event = if !was_locked && client.locked() {
+            Some(LockEvent {
+                client: id,
+                tx,
+                snapshot: ClientOutput::from(&*client),
+            })
+        } else {
+            None
+        };
+        Ok(Ok(event))
+    }
+
+    /// Apply 'txs' to a copy of the clients they reference, returning the resulting state
+    /// of those clients sorted by id without modifying the atm.
+    pub fn simulate(&self, txs: &[Transaction]) -> Result<Vec<ClientSnapshot>> {
+        let ids: BTreeSet<u32> = txs.iter().map(|t| t.client).collect();
+        let mut scratch = Atm {
+            clients: ids
+                .iter()
+                .filter_map(|id| self.clients.get(id).map(|client| (*id, client.clone())))
+                .collect(),
+            config: self.config.clone(),
+            report: ProcessingReport::default(),
+            seen_txs: self.seen_txs.clone(),
+            ignored: HashMap::new(),
+            events: Vec::new(),
+        };
+        for t in txs {
+            scratch.execute(t.clone())?;
+        }
+        Ok(ids
+            .iter()
+            .filter_map(|id| scratch.clients.get(id).map(ClientOutput::from))
+            .collect())
+    }
+
+    /// Check transactions in a csv format without modifying the atm.
+    ///
+    /// The transactions are applied to a copy of the current state, and every row that
+    /// can't be read or would be ignored is reported instead of stopping at the first error.
+    pub fn validate<R: io::Read>(&self, reader: R) -> Result<ValidationReport> {
+        let mut scratch = Atm {
+            clients: self.clients.clone(),
+            config: self.config.clone(),
+            report: ProcessingReport::default(),
+            seen_txs: self.seen_txs.clone(),
+            ignored: HashMap::new(),
+            events: Vec::new(),
+        };
+        let mut reader = TransactionReader::new(reader, &scratch.config)?;
+        let mut report = ValidationReport::default();
+        loop {
+            let (line, diagnostic) = match reader.next_row() {
+                Ok(None) => break,
+                Ok(Some((line, Row::Skipped(reason)))) => (line, Diagnostic::Error(reason)),
+                Ok(Some((line, Row::Transaction(transaction)))) => match scratch.apply(transaction)
+                {
+                    Ok(Ok(_)) => continue,
+                    Ok(Err(reason)) => (line, Diagnostic::Warning(reason)),
+                    Err(err) => (line, Diagnostic::Error(format!("{:#}", err))),
+                },
+                Err(err) => {
+                    // The input can't be read further.
+                    if let Some(true) = err
+                        .downcast_ref::<csv::Error>()
+                        .map(csv::Error::is_io_error)
+                    {
+                        return Err(err);
+                    }
+                    (reader.line(), Diagnostic::Error(format!("{:#}", err)))
+                }
+            };
+            report.diagnostics.insert(line, diagnostic);
+        }
+        Ok(report)
+    }
+
+    fn ignore(&mut self, client: u32, tx: u32, reason: IgnoreReason) -> Result<()> {
+        if self.config.fail_fast {
+            bail!("Ignored tx {} for client {}: {}", tx, client, reason);
+        }
+        self.tally_ignored(client, tx, reason);
+        Ok(())
+    }
+
+    fn tally_ignored(&mut self, client: u32, tx: u32, reason: IgnoreReason) {
+        self.report.skipped += 1;
+        if reason == IgnoreReason::Locked {
+            self.report.rejected_due_to_lock += 1;
+        }
+        if self.config.record_ignored {
+            self.ignored.insert((client, tx), reason);
+        }
+        if self.config.verbose {
+            eprintln!("Ignored tx {} for client {}: {}", tx, client, reason);
+        }
     }
 
     /// Print the state of clients in an csv format to stdout.
     pub fn print_csv(&self) -> Result<()> {
-        let mut writer = Writer::from_writer(io::stdout());
-        serialize(self, &mut writer)
+        self.write_csv(io::stdout(), &OutputOptions::default())
+    }
+
+    /// Write the state of clients in an csv format to 'writer'.
+    pub fn write_csv<W: io::Write>(&self, writer: W, options: &OutputOptions) -> Result<()> {
+        if !options.checksum {
+            let mut writer = csv_writer(writer, options)?;
+            serialize(self, &mut writer, options)?;
+            return Ok(());
+        }
+        let mut writer = csv_writer(HashingWriter::new(writer), options)?;
+        let clients = serialize(self, &mut writer, options)?;
+        write_checksum(writer, clients)
     }
 
     /// Write the state of clients in an csv format to a string.
-    #[allow(dead_code)]
     pub fn to_csv_string(&self) -> Result<String> {
-        let mut writer = Writer::from_writer(vec![]);
-        serialize(self, &mut writer)?;
-        Ok(String::from_utf8(writer.into_inner()?)?)
+        self.to_csv_string_with(&OutputOptions::default())
+    }
+
+    /// Write the state of clients in an csv format to a string, using 'options'.
+    pub fn to_csv_string_with(&self, options: &OutputOptions) -> Result<String> {
+        let mut buf = Vec::new();
+        self.write_csv(&mut buf, options)?;
+        Ok(String::from_utf8(buf)?)
+    }
+
+    /// Write the state of clients in an csv format to the file at 'path', using 'options'.
+    ///
+    /// The output is streamed through a buffer and never held in memory as a whole.
+    pub fn write_csv_to_path<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &OutputOptions,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        self.write_csv(io::BufWriter::new(file), options)
+    }
+
+    /// Save the full state of clients, including their txs, in a csv format to 'writer'.
+    ///
+    /// Clients are sorted by id to make the output deterministic, and their txs are in the
+    /// order they arrived.
+    pub fn save_state<W: io::Write>(&self, writer: W) -> Result<()> {
+        let mut writer = Writer::from_writer(writer);
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        clients.sort_by_key(|client| client.id());
+        for client in clients {
+            for row in client.state_rows() {
+                writer.serialize(row)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Load a state saved by `save_state` from 'reader', replacing the state of the clients in it.
+    ///
+    /// Tx rows belong to the client row preceding them. The state isn't sanity checked.
+    pub fn load_state<R: io::Read>(&mut self, reader: R) -> Result<()> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(reader);
+        for row in reader.deserialize() {
+            let row: StateRow = row?;
+            if row.record == RecordKind::Client {
+                self.clients.insert(row.client, Client::from_state(&row)?);
+            } else {
+                if let Some(tx) = row.tx {
+                    self.seen_txs.insert(tx);
+                }
+                self.clients
+                    .get_mut(&row.client)
+                    .ok_or_else(|| anyhow!("Found tx {:?} before client {}", row.tx, row.client))?
+                    .restore_tx(&row)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the invariants of all clients, reporting every violation sorted by client id.
+    pub fn check_invariants(&self) -> std::result::Result<(), Vec<Violation>> {
+        let violations = self.violations(|client| client.violations(&self.config));
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+
+    /// Clients with balances of more decimal places than the configured precision,
+    /// sorted by client id.
+    ///
+    /// Amounts are rounded when applied, so this indicates a precision leak.
+    pub fn precision_violations(&self) -> Vec<Violation> {
+        self.violations(|client| client.precision_violations(self.config.precision))
+    }
+
+    fn violations<F>(&self, check: F) -> Vec<Violation>
+    where
+        F: Fn(&Client) -> Vec<ViolationKind>,
+    {
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        clients.sort_by_key(|client| client.id());
+        clients
+            .into_iter()
+            .flat_map(|client| {
+                check(client).into_iter().map(move |kind| Violation {
+                    client: client.id(),
+                    kind,
+                })
+            })
+            .collect()
+    }
+
+    /// A hash of the full internal state, including txs and their dispute status.
+    ///
+    /// Clients and txs are hashed in order of their ids, so identical runs give the same
+    /// fingerprint. It isn't stable across Rust versions and shouldn't be persisted.
+    pub fn state_fingerprint(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let mut clients: Vec<&Client> = self.clients.values().collect();
+        clients.sort_by_key(|client| client.id());
+        for client in clients {
+            client.hash_state(&mut hasher);
+        }
+        let mut seen_txs: Vec<u32> = self.seen_txs.iter().copied().collect();
+        seen_txs.sort_unstable();
+        seen_txs.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Compare the state of clients against an expected output in a csv format.
+    ///
+    /// Returns the clients that differ, sorted by client id.
+    pub fn reconcile<R: io::Read>(&self, expected: R) -> Result<Vec<Discrepancy>> {
+        let mut reader = ReaderBuilder::new().trim(Trim::All).from_reader(expected);
+        let mut expected = HashMap::new();
+        for row in reader.deserialize() {
+            let row: ClientOutput = row?;
+            expected.insert(row.client, row);
+        }
+
+        let ids: BTreeSet<u32> = expected
+            .keys()
+            .chain(self.clients.keys())
+            .copied()
+            .collect();
+        let discrepancies = ids
+            .into_iter()
+            .filter_map(|id| {
+                let expected = expected.remove(&id);
+                let actual = self.clients.get(&id).map(ClientOutput::from);
+                if expected == actual {
+                    None
+                } else {
+                    Some(Discrepancy {
+                        client: id,
+                        expected,
+                        actual,
+                    })
+                }
+            })
+            .collect();
+        Ok(discrepancies)
     }
 }
 
-fn serialize<W: io::Write>(atm: &Atm, writer: &mut Writer<W>) -> Result<()> {
+fn serialize<W: io::Write>(
+    atm: &Atm,
+    writer: &mut Writer<W>,
+    options: &OutputOptions,
+) -> Result<usize> {
+    let mut written = 0;
     for client in atm.clients.values() {
-        writer.serialize(client)?;
+        if write_row(writer, client, options)? {
+            written += 1;
+        }
     }
     writer.flush()?;
+    Ok(written)
+}
+
+/// A writer for the client output, with the header written up front.
+fn csv_writer<W: io::Write>(writer: W, options: &OutputOptions) -> Result<Writer<W>> {
+    let mut builder = WriterBuilder::new();
+    builder.delimiter(options.delimiter).quote(options.quote);
+    // The header is written up front, rather than with the first row, to be written
+    // without any clients too.
+    let mut writer = builder.has_headers(false).from_writer(writer);
+    let header: Vec<String> = ClientOutput::columns(options)
+        .iter()
+        .map(|column| {
+            if options.uppercase_header {
+                column.to_uppercase()
+            } else {
+                column.to_string()
+            }
+        })
+        .collect();
+    writer.write_record(&header)?;
+    Ok(writer)
+}
+
+/// Write the `# clients=N sha256=...` footer after the rows written through 'writer'.
+fn write_checksum<W: io::Write>(writer: Writer<HashingWriter<W>>, clients: usize) -> Result<()> {
+    let (mut writer, digest) = writer
+        .into_inner()
+        .map_err(|err| anyhow!("Failed to write the output: {}", err.error()))?
+        .finish();
+    writeln!(writer, "# clients={} sha256={}", clients, digest)?;
+    writer.flush()?;
     Ok(())
 }
+
+fn write_row<W: io::Write>(
+    writer: &mut Writer<W>,
+    client: &Client,
+    options: &OutputOptions,
+) -> Result<bool> {
+    // Balances without txs can only come from a loaded state.
+    let untracked = !client.has_txs() && client.total() != Decimal::ZERO;
+    if untracked && options.skip_balances_without_txs {
+        return Ok(false);
+    }
+    writer.serialize(ClientOutput::new(client, options))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crate::builder::AtmBuilder;
+    use crate::client::TxKind;
+    use crate::config::{Aliases, DisputePolicy, LockedFormat};
+
+    impl Atm {
+        /// The csv output with clients sorted by id.
+        fn to_csv_string_sorted(&self) -> String {
+            let mut csv = String::from("client,available,held,total,locked\n");
+            for (client, available, held, total, locked) in self.balances() {
+                csv.push_str(&format!(
+                    "{},{},{},{},{}\n",
+                    client, available, held, total, locked
+                ));
+            }
+            csv
+        }
+    }
+
+    #[test]
+    fn custom_aliases() {
+        let config = Config {
+            aliases: Aliases::new()
+                .header("kind", "type")
+                .type_name("credit", "deposit"),
+            ..Config::default()
+        };
+        let mut atm = Atm::with_config(config);
+        atm.process_reader("kind,client,tx,amount\nCredit,1,1,2.5\n".as_bytes())
+            .unwrap();
+        assert_eq!(
+            atm.to_csv_string().unwrap(),
+            "client,available,held,total,locked\n1,2.5,0,2.5,false\n"
+        );
+    }
+
+    #[test]
+    fn capitalized_header() {
+        let mut atm = Atm::new();
+        atm.process_reader("Type,Client,Tx,Amount\ndeposit,1,1,2.5\n".as_bytes())
+            .unwrap();
+        assert_eq!(atm.balances()[0].1, Decimal::new(25, 1));
+    }
+
+    #[test]
+    fn render_discrepancy() {
+        let mut atm = Atm::new();
+        atm.process_reader("type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\n".as_bytes())
+            .unwrap();
+        let expected = "client,available,held,total,locked\n1,2.0,0,2.0,false\n2,1.5,0,1.5,false\n";
+        let discrepancies = atm.reconcile(expected.as_bytes()).unwrap();
+        assert_eq!(discrepancies.len(), 1);
+        assert_eq!(
+            discrepancies[0].render(),
+            "@@ client 2 @@\n-2,1.5,0,1.5,false\n+2,1,0,1,false\n"
+        );
+    }
+
+    #[test]
+    fn held_invariant_catches_withdrawal_disputes() {
+        // Disputing withdrawals holds funds without removing them from available,
+        // so disputing an overdrawing withdrawal holds more than was ever deposited.
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            withdrawal,1,2,2.0
+            dispute,1,2,";
+        let config = Config {
+            overdraft_limit: Decimal::ONE,
+            ..Config::default()
+        };
+
+        Atm::with_config(config.clone())
+            .process_reader(input.as_bytes())
+            .unwrap();
+
+        let mut atm = Atm::with_config(Config {
+            check_held_invariant: true,
+            ..config
+        });
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Failed held within deposits invariant check"));
+    }
+
+    #[test]
+    fn aggregate_clients() {
+        let mut atm = Atm::new();
+        let input = "type,client,tx,amount
+            deposit,1,1,2.0
+            deposit,2,2,3.0
+            dispute,2,2,
+            chargeback,2,2,
+            deposit,3,3,1.5";
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let total: Decimal = atm.clients_iter().map(Client::total).sum();
+        assert_eq!(total, Decimal::new(35, 1));
+        assert_eq!(atm.clients_iter().filter(|c| c.locked()).count(), 1);
+    }
+
+    fn locked_output(format: LockedFormat) -> String {
+        let mut atm = Atm::new();
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            dispute,1,1,
+            chargeback,1,1,";
+        atm.process_reader(input.as_bytes()).unwrap();
+        atm.to_csv_string_with(&OutputOptions {
+            locked: format,
+            ..OutputOptions::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn locked_true_false() {
+        assert_eq!(
+            locked_output(LockedFormat::TrueFalse),
+            "client,available,held,total,locked\n1,0,0,0,true\n"
+        );
+    }
+
+    #[test]
+    fn locked_one_zero() {
+        assert_eq!(
+            locked_output(LockedFormat::OneZero),
+            "client,available,held,total,locked\n1,0,0,0,1\n"
+        );
+    }
+
+    #[test]
+    fn locked_yes_no() {
+        assert_eq!(
+            locked_output(LockedFormat::YesNo),
+            "client,available,held,total,locked\n1,0,0,0,yes\n"
+        );
+    }
+
+    #[test]
+    fn lock_events() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            deposit,2,2,2.0
+            deposit,2,3,1.0
+            dispute,1,1,
+            chargeback,1,1,
+            dispute,2,2,
+            resolve,2,2,
+            dispute,2,3,
+            chargeback,2,3,";
+        let mut events = Vec::new();
+        Atm::new()
+            .process_reader_with_locks(input.as_bytes(), |event| events.push(event))
+            .unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].client, events[0].tx), (1, 1));
+        assert_eq!((events[1].client, events[1].tx), (2, 3));
+        assert_eq!(events[1].snapshot.available, Decimal::new(2, 0));
+        assert!(events[1].snapshot.locked);
+    }
+
+    #[test]
+    fn extended_output_counts() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            deposit,1,2,1.0
+            withdrawal,1,3,2.0
+            withdrawal,1,4,100.0
+            dispute,1,2,
+            resolve,1,2,
+            dispute,1,2,
+            chargeback,1,2,
+            dispute,1,9,";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let options = OutputOptions {
+            extended: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            atm.to_csv_string_with(&options).unwrap(),
+            "client,available,held,total,locked,\
+             deposits,withdrawals,disputes,resolves,chargebacks,corrections,unlocks,adjustments,\
+             disputed\n\
+             1,3,0,3,true,2,1,2,1,1,0,0,0,0\n"
+        );
+        assert_eq!(
+            atm.to_csv_string().unwrap(),
+            "client,available,held,total,locked\n1,3,0,3,true\n"
+        );
+    }
+
+    #[test]
+    fn require_decimal_point() {
+        let input = "type,client,tx,amount
+            deposit,1,1,100
+            deposit,1,2,100.00
+            dispute,1,2,";
+        let mut atm = AtmBuilder::new().require_decimal_point(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().skipped, 1);
+        assert_eq!(
+            atm.to_csv_string().unwrap(),
+            "client,available,held,total,locked\n1,0,100,100,false\n"
+        );
+
+        let mut atm = AtmBuilder::new()
+            .require_decimal_point(true)
+            .strict(true)
+            .build();
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Amount \"100\" without a decimal point on line 2: deposit,1,1,100"
+        );
+
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().skipped, 0);
+
+        // Amounts of rows without one aren't used, so they don't need a decimal point.
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            dispute,1,1,5";
+        let mut atm = AtmBuilder::new().require_decimal_point(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().skipped, 0);
+        assert_eq!(
+            atm.to_csv_string().unwrap(),
+            "client,available,held,total,locked\n1,0,5,5,false\n"
+        );
+
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0";
+        let mut atm = AtmBuilder::new()
+            .has_headers(false)
+            .require_decimal_point(true)
+            .strict(true)
+            .build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().skipped, 1);
+        assert_eq!(atm.report().processed, 1);
+    }
+
+    #[test]
+    fn strict_rejects_dispute_with_amount() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,1.0\n";
+        Atm::new().process_reader(input.as_bytes()).unwrap();
+
+        let mut atm = Atm::with_config(Config {
+            strict: true,
+            ..Config::default()
+        });
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unexpected amount \"1.0\" for Dispute on line 3"
+        );
+    }
+
+    #[test]
+    fn strict_rejects_control_rows_with_amount() {
+        let path = Path::new("test_files/strict_amounts.csv");
+        let atm = Atm::from_path(path).unwrap();
+        assert_eq!(
+            atm.to_csv_string().unwrap(),
+            "client,available,held,total,locked\n1,0,0,0,true\n"
+        );
+
+        let err = AtmBuilder::new().strict(true).from_path(path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unexpected amount \"2.0\" for Resolve on line 4"
+        );
+    }
+
+    #[test]
+    fn balances_sorted_by_client() {
+        let input = "type,client,tx,amount
+            deposit,2,1,2.0
+            deposit,1,2,1.5
+            dispute,2,1,";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![
+                (
+                    1,
+                    Decimal::new(15, 1),
+                    Decimal::ZERO,
+                    Decimal::new(15, 1),
+                    false
+                ),
+                (
+                    2,
+                    Decimal::ZERO,
+                    Decimal::new(2, 0),
+                    Decimal::new(2, 0),
+                    false
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_counts() {
+        let atm = Atm::from_path(Path::new("test_files/mixed.in")).unwrap();
+        assert_eq!(
+            atm.report(),
+            &ProcessingReport {
+                processed: 5,
+                skipped: 4,
+                ..ProcessingReport::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rejected_due_to_lock() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            dispute,1,1,
+            chargeback,1,1,
+            deposit,1,2,5.0
+            withdrawal,1,3,1.0
+            deposit,2,4,1.0
+            withdrawal,2,5,2.0";
+        let mut atm = AtmBuilder::new().reject_locked(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.report(),
+            &ProcessingReport {
+                processed: 4,
+                skipped: 3,
+                rejected_due_to_lock: 2,
+                ..ProcessingReport::default()
+            }
+        );
+
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().rejected_due_to_lock, 0);
+    }
+
+    #[test]
+    fn fail_fast_on_underfunded_withdrawal() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            withdrawal,1,2,2.0
+            deposit,1,3,5.0";
+        let mut atm = Atm::with_config(Config {
+            fail_fast: true,
+            ..Config::default()
+        });
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Ignored tx 2 for client 1: insufficient funds"
+        );
+        assert_eq!(atm.balances()[0].1, Decimal::ONE);
+    }
+
+    #[test]
+    fn chargeback_after_load_state() {
+        let mut atm = Atm::new();
+        let first = "type,client,tx,amount
+            deposit,1,1,2.0
+            deposit,1,2,1.0
+            dispute,1,1,";
+        atm.process_reader(first.as_bytes()).unwrap();
+        let mut state = Vec::new();
+        atm.save_state(&mut state).unwrap();
+
+        let mut atm = Atm::new();
+        atm.load_state(state.as_slice()).unwrap();
+        atm.process_reader("type,client,tx,amount\nchargeback,1,1,\n".as_bytes())
+            .unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![(1, Decimal::ONE, Decimal::ZERO, Decimal::ONE, true)]
+        );
+    }
+
+    #[test]
+    fn load_state_keeps_arrival_order() {
+        let mut atm = Atm::new();
+        let input = "type,client,tx,amount
+            deposit,1,5,1.0
+            deposit,1,2,1.0
+            deposit,1,9,1.0";
+        atm.process_reader(input.as_bytes()).unwrap();
+        let mut state = Vec::new();
+        atm.save_state(&mut state).unwrap();
+
+        let mut loaded = Atm::new();
+        loaded.load_state(state.as_slice()).unwrap();
+        let ids = |atm: &Atm| -> Vec<u32> {
+            let client = atm.clients_iter().next().unwrap();
+            client
+                .transactions_of(TxKind::Deposit)
+                .iter()
+                .map(|tx| tx.id())
+                .collect()
+        };
+        assert_eq!(ids(&loaded), vec![5, 2, 9]);
+        assert_eq!(ids(&loaded), ids(&atm));
+    }
+
+    #[test]
+    fn save_state_format() {
+        let mut atm = Atm::new();
+        let input = "type,client,tx,amount
+            deposit,2,3,1.0
+            deposit,1,1,2.0
+            withdrawal,1,2,0.5
+            dispute,1,2,";
+        atm.process_reader(input.as_bytes()).unwrap();
+        let mut state = Vec::new();
+        atm.save_state(&mut state).unwrap();
+        assert_eq!(
+            String::from_utf8(state).unwrap(),
+            "record,client,available,held,locked,tx,amount,disputed,reversed,currency,charged_back
+client,1,1.5,0.5,false,,,,,,
+deposit,1,,,,1,2,false,false,,false
+withdrawal,1,,,,2,0.5,true,false,,false
+client,2,1,0,false,,,,,,
+deposit,2,,,,3,1,false,false,,false
+"
+        );
+    }
+
+    #[test]
+    fn replayed_file_is_skipped() {
+        let path = Path::new("test_files/base-input.in");
+        let once = Atm::from_path(path).unwrap();
+        let mut twice = Atm::from_path(path).unwrap();
+        twice.process_path(path).unwrap();
+
+        assert_eq!(twice.balances(), once.balances());
+        assert_eq!(twice.report().skipped, once.report().skipped + 5);
+    }
+
+    /// Tracks how many bytes have been read.
+    struct CountingReader<'a> {
+        input: &'a [u8],
+        read: Rc<Cell<usize>>,
+    }
+
+    impl io::Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.input.read(buf)?;
+            self.read.set(self.read.get() + n);
+            Ok(n)
+        }
+    }
+
+    /// Records how many bytes had been read from the input at the first write.
+    struct FirstWriteWriter {
+        output: Vec<u8>,
+        read: Rc<Cell<usize>>,
+        read_at_first_write: Option<usize>,
+    }
+
+    impl io::Write for FirstWriteWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.read_at_first_write.get_or_insert(self.read.get());
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn stream_sorted_writes_before_input_is_consumed() {
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut tx = 0;
+        for client in 1..=3 {
+            for _ in 0..1000 {
+                tx += 1;
+                input.push_str(&format!("deposit,{},{},1.0\n", client, tx));
+            }
+            input.push_str(&format!("withdrawal,{},{},0.5\n", client, tx + 1));
+            input.push_str(&format!("dispute,{},{},\n", client, tx));
+            tx += 1;
+        }
+
+        let read = Rc::new(Cell::new(0));
+        let reader = CountingReader {
+            input: input.as_bytes(),
+            read: read.clone(),
+        };
+        let mut writer = FirstWriteWriter {
+            output: Vec::new(),
+            read,
+            read_at_first_write: None,
+        };
+        Atm::new()
+            .stream_sorted(reader, &mut writer, &OutputOptions::default())
+            .unwrap();
+
+        assert!(writer.read_at_first_write.unwrap() < input.len());
+        let mut expected = Atm::new();
+        expected.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.output).unwrap(),
+            expected.to_csv_string_sorted()
+        );
+    }
+
+    #[test]
+    fn stream_sorted_rejects_unsorted_input() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            deposit,2,2,1.0
+            deposit,1,3,1.0";
+        let err = Atm::new()
+            .stream_sorted(input.as_bytes(), io::sink(), &OutputOptions::default())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Transactions for client 1 aren't contiguous"
+        );
+    }
+
+    #[test]
+    fn stream_sorted_settles_eof_disputes() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            dispute,1,1,
+            deposit,2,2,2.0
+            dispute,2,2,";
+        let mut output = Vec::new();
+        AtmBuilder::new()
+            .eof_disputes(EofDisputePolicy::Resolve)
+            .build()
+            .stream_sorted(input.as_bytes(), &mut output, &OutputOptions::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n1,1,0,1,false\n2,2,0,2,false\n"
+        );
+    }
+
+    #[test]
+    fn stream_sorted_reports_unresolved_disputes() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            deposit,1,2,1.0
+            dispute,1,2,
+            dispute,1,1,
+            deposit,2,3,2.0
+            deposit,3,4,2.0
+            dispute,3,4,";
+        let mut atm = AtmBuilder::new().report_unresolved_disputes(true).build();
+        atm.stream_sorted(input.as_bytes(), io::sink(), &OutputOptions::default())
+            .unwrap();
+        assert_eq!(
+            atm.report().unresolved_disputes,
+            vec![(1, 1), (1, 2), (3, 4)]
+        );
+    }
+
+    #[test]
+    fn precision_violations() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.123456
+            withdrawal,1,2,0.00005";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert!(atm.precision_violations().is_empty());
+
+        let state = "record,client,available,held,locked,tx,amount,disputed,reversed
+            client,1,1.00005,0.5,false,,,,
+            client,2,1.5,0.12345,false,,,,
+            client,3,2.1000,0,false,,,,";
+        let mut atm = Atm::new();
+        atm.load_state(state.as_bytes()).unwrap();
+        assert_eq!(
+            atm.precision_violations(),
+            vec![
+                Violation {
+                    client: 1,
+                    kind: ViolationKind::AvailablePrecision(Decimal::new(100005, 5)),
+                },
+                Violation {
+                    client: 2,
+                    kind: ViolationKind::HeldPrecision(Decimal::new(12345, 5)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn release_more_than_held() {
+        let state = "record,client,available,held,locked,tx,amount,disputed,reversed
+            client,1,0,0.5,false,,,,
+            deposit,1,,,,1,2.0,true,false
+            client,2,0,0.5,false,,,,
+            deposit,2,,,,2,2.0,true,false";
+        let input = "type,client,tx,amount
+            resolve,1,1,
+            chargeback,2,2,";
+        let mut atm = AtmBuilder::new().record_ignored(true).build();
+        atm.load_state(state.as_bytes()).unwrap();
+        atm.process_reader(input.as_bytes()).unwrap();
+        let half = Decimal::new(5, 1);
+        assert_eq!(
+            atm.balances(),
+            vec![
+                (1, Decimal::ZERO, half, half, false),
+                (2, Decimal::ZERO, half, half, false),
+            ]
+        );
+        assert_eq!(atm.why_ignored(1, 1), Some(IgnoreReason::InsufficientHeld));
+        assert_eq!(atm.why_ignored(2, 2), Some(IgnoreReason::InsufficientHeld));
+    }
+
+    #[test]
+    fn load_state_rejects_disputed_adjustment() {
+        let state = "record,client,available,held,locked,tx,amount,disputed,reversed
+            client,1,0,1.0,false,,,,
+            adjustment,1,,,,1,1.0,true,false";
+        let err = Atm::new().load_state(state.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Adjustment tx 1 can't be disputed or charged back"
+        );
+
+        let state = "record,client,available,held,locked,tx,amount,disputed,reversed,charged_back
+            client,1,1.0,0,true,,,,,
+            adjustment,1,,,,1,1.0,false,false,true";
+        assert!(Atm::new().load_state(state.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn semicolon_delimiter() {
+        let atm = AtmBuilder::new()
+            .delimiter(b';')
+            .quote(b'\'')
+            .from_path(Path::new("test_files/semicolon.csv"))
+            .unwrap();
+        let output = atm.to_csv_string().unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            vec![
+                "1,1.5,0,1.5,false",
+                "2,0,2,2,false",
+                "client,available,held,total,locked"
+            ]
+        );
+
+        let options = OutputOptions {
+            delimiter: b'\t',
+            ..OutputOptions::default()
+        };
+        assert!(atm
+            .to_csv_string_with(&options)
+            .unwrap()
+            .starts_with("client\tavailable\theld\ttotal\tlocked\n"));
+    }
+
+    #[test]
+    fn process_source() {
+        let transactions = vec![
+            Transaction {
+                client: 1,
+                tx: 1,
+                variant: TransactionVariant::Deposit {
+                    amount: Decimal::new(5, 0),
+                },
+                currency: None,
+            },
+            Transaction {
+                client: 1,
+                tx: 2,
+                variant: TransactionVariant::Withdrawal {
+                    amount: Decimal::new(15, 1),
+                },
+                currency: None,
+            },
+            Transaction {
+                client: 2,
+                tx: 3,
+                variant: TransactionVariant::Deposit {
+                    amount: Decimal::ONE,
+                },
+                currency: None,
+            },
+            Transaction {
+                client: 2,
+                tx: 3,
+                variant: TransactionVariant::Dispute,
+                currency: None,
+            },
+        ];
+        let mut atm = Atm::new();
+        atm.process_source(transactions.into_iter().map(Ok))
+            .unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![
+                (
+                    1,
+                    Decimal::new(35, 1),
+                    Decimal::ZERO,
+                    Decimal::new(35, 1),
+                    false
+                ),
+                (2, Decimal::ZERO, Decimal::ONE, Decimal::ONE, false),
+            ]
+        );
+
+        let skipped: Vec<Result<Transaction>> = vec![Err(SkippedRow {
+            line: 7,
+            reason: "Bad row".to_string(),
+        }
+        .into())];
+        atm.process_source(skipped.into_iter()).unwrap();
+        assert_eq!(atm.report().skipped, 1);
+    }
+
+    #[test]
+    fn unresolved_disputes() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            deposit,1,2,1.0
+            deposit,2,3,2.0
+            dispute,1,1,
+            dispute,1,2,
+            dispute,2,3,
+            resolve,1,1,
+            chargeback,2,3,";
+        let mut atm = AtmBuilder::new().report_unresolved_disputes(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        atm.finish().unwrap();
+        assert_eq!(atm.report().unresolved_disputes, vec![(1, 2)]);
+
+        atm.process_reader("type,client,tx,amount\nresolve,1,2,".as_bytes())
+            .unwrap();
+        atm.finish().unwrap();
+        assert!(atm.report().unresolved_disputes.is_empty());
+
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        atm.finish().unwrap();
+        assert!(atm.report().unresolved_disputes.is_empty());
+    }
+
+    #[test]
+    fn eof_disputes() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            deposit,1,2,1.0
+            dispute,1,1,
+            dispute,1,2,
+            resolve,1,2,";
+        let process = |policy| {
+            let mut atm = AtmBuilder::new()
+                .eof_disputes(policy)
+                .report_unresolved_disputes(true)
+                .build();
+            atm.process_reader(input.as_bytes()).unwrap();
+            atm.finish().unwrap();
+            atm
+        };
+
+        let atm = process(EofDisputePolicy::Keep);
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::ONE,
+                Decimal::new(5, 0),
+                Decimal::new(6, 0),
+                false
+            )]
+        );
+        assert_eq!(atm.report().unresolved_disputes, vec![(1, 1)]);
+
+        let atm = process(EofDisputePolicy::Resolve);
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::new(6, 0),
+                Decimal::ZERO,
+                Decimal::new(6, 0),
+                false
+            )]
+        );
+        assert!(atm.report().unresolved_disputes.is_empty());
+
+        let atm = process(EofDisputePolicy::Chargeback);
+        assert_eq!(
+            atm.balances(),
+            vec![(1, Decimal::ONE, Decimal::ZERO, Decimal::ONE, true)]
+        );
+    }
+
+    #[test]
+    fn eof_disputes_settle_after_all_inputs() {
+        let mut atm = AtmBuilder::new()
+            .eof_disputes(EofDisputePolicy::Chargeback)
+            .build();
+        atm.process_reader("type,client,tx,amount\ndeposit,1,1,10.0\ndispute,1,1,".as_bytes())
+            .unwrap();
+        atm.process_reader("type,client,tx,amount\nresolve,1,1,".as_bytes())
+            .unwrap();
+        atm.finish().unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::new(10, 0),
+                Decimal::ZERO,
+                Decimal::new(10, 0),
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn deadline() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 0..10_000 {
+            input.push_str(&format!("deposit,1,{},1.0\n", tx));
+        }
+
+        let (atm, truncated) =
+            Atm::from_reader_with_deadline(input.as_bytes(), Duration::ZERO).unwrap();
+        assert!(truncated);
+        assert!(atm.report().processed < 10_000);
+
+        let (atm, truncated) =
+            Atm::from_reader_with_deadline(input.as_bytes(), Duration::from_secs(3600)).unwrap();
+        assert!(!truncated);
+        assert_eq!(atm.report().processed, 10_000);
+    }
+
+    #[test]
+    fn why_ignored() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            withdrawal,1,2,5.0
+            dispute,1,3,
+            deposit,2,1,1.0
+            resolve,1,1,
+            dispute,1,1,";
+        let mut atm = AtmBuilder::new().record_ignored(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.why_ignored(1, 1), None);
+        assert_eq!(atm.why_ignored(1, 2), Some(IgnoreReason::InsufficientFunds));
+        assert_eq!(atm.why_ignored(1, 3), Some(IgnoreReason::UnknownTx));
+        assert_eq!(atm.why_ignored(2, 1), Some(IgnoreReason::DuplicateTx));
+
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.why_ignored(1, 2), None);
+    }
+
+    #[test]
+    fn buffered_keeps_order() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=2000u32 {
+            let client = tx % 7;
+            input.push_str(&format!("deposit,{},{},1.5\n", client, tx));
+            match tx % 5 {
+                0 => input.push_str(&format!("withdrawal,{},{},{}\n", client, tx + 10_000, tx)),
+                1 => input.push_str(&format!("dispute,{},{},\n", client, tx)),
+                2 => input.push_str(&format!("chargeback,{},{},\n", client, tx - 1)),
+                3 => input.push_str(&format!("resolve,{},{},\n", client, tx - 2)),
+                _ => {}
+            }
+        }
+
+        let mut buffered = AtmBuilder::new().channel_capacity(1).build();
+        buffered.process_buffered(input.as_bytes()).unwrap();
+        let mut sequential = Atm::new();
+        sequential.process_reader(input.as_bytes()).unwrap();
+
+        assert_eq!(buffered.balances(), sequential.balances());
+        assert_eq!(buffered.report(), sequential.report());
+        assert_eq!(buffered.state_fingerprint(), sequential.state_fingerprint());
+
+        let err = AtmBuilder::new()
+            .channel_capacity(1)
+            .fail_fast(true)
+            .build()
+            .process_buffered(input.as_bytes())
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Ignored tx"));
+    }
+
+    #[test]
+    fn parallel_keeps_client_order() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for client in 1..=50u32 {
+            let tx = client * 10;
+            input.push_str(&format!("deposit,{},{},{}.5\n", client, tx, client));
+            input.push_str(&format!("dispute,{},{},\n", client, tx));
+            input.push_str(&format!("deposit,{},{},1.0\n", client, tx + 1));
+            if client % 3 == 0 {
+                input.push_str(&format!("resolve,{},{},\n", client, tx));
+            }
+        }
+        input.push_str("deposit,2,10,1.0\n");
+
+        let mut parallel = Atm::new();
+        parallel
+            .process_reader_parallel(input.as_bytes(), 4)
+            .unwrap();
+        let mut sequential = Atm::new();
+        sequential.process_reader(input.as_bytes()).unwrap();
+
+        assert_eq!(parallel.balances(), sequential.balances());
+        assert_eq!(parallel.report(), sequential.report());
+        assert_eq!(parallel.report().skipped, 1);
+        let (_, available, held, _, _) = parallel.balances()[0];
+        assert_eq!((available, held), (Decimal::ONE, Decimal::new(15, 1)));
+    }
+
+    #[test]
+    fn records() {
+        let input = "type,client,tx,amount
+            deposit,2,1,10.12345
+            deposit,1,2,3.0
+            withdrawal,1,3,0.25
+            deposit,1,4,1.5
+            dispute,1,4,";
+        let mut atm = AtmBuilder::new().precision(2).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let record = |client, available, held| ClientRecord {
+            client,
+            available,
+            held,
+            total: available + held,
+            scale: 2,
+            locked: false,
+        };
+        let records = atm.records();
+        assert_eq!(records, vec![record(1, 275, 150), record(2, 1012, 0)]);
+
+        let mut buf = [ClientRecord::default(); 1];
+        assert_eq!(atm.fill_records(&mut buf), 1);
+        assert_eq!(buf[0], records[0]);
+        let mut buf = [ClientRecord::default(); 3];
+        assert_eq!(atm.fill_records(&mut buf), 2);
+        assert_eq!(buf[2], ClientRecord::default());
+    }
+
+    #[test]
+    fn workers() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for i in 0..5000u32 {
+            let client = i % 97;
+            input.push_str(&format!("deposit,{},{},{}.{}\n", client, i, i % 50, i % 7));
+            match i % 5 {
+                0 => input.push_str(&format!("dispute,{},{},\n", client, i)),
+                1 => input.push_str(&format!("withdrawal,{},{},25\n", client, i + 100_000)),
+                2 => input.push_str(&format!("chargeback,{},{},\n", client, i - 2)),
+                3 => input.push_str(&format!("deposit,{},{},1\n", client, i / 2)),
+                _ => input.push_str(&format!("unlock,{},{},\n", client, i)),
+            }
+        }
+        let process = |workers| {
+            let mut atm = AtmBuilder::new().workers(workers).event_log(true).build();
+            atm.process_reader(input.as_bytes()).unwrap();
+            atm
+        };
+
+        let (one, four) = (process(1), process(4));
+        assert_eq!(one.balances(), four.balances());
+        assert_eq!(one.report(), four.report());
+        assert_eq!(one.events(), four.events());
+        assert_eq!(one.state_fingerprint(), four.state_fingerprint());
+        assert!(one.report().skipped > 0);
+    }
+
+    #[test]
+    fn check_invariants() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            withdrawal,1,2,1.5
+            dispute,1,2,
+            deposit,2,3,2.0
+            dispute,2,3,
+            chargeback,2,3,";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.check_invariants(), Ok(()));
+
+        let state = "record,client,available,held,locked,tx,amount,disputed,reversed
+            client,1,-1.0,2.0,false,,,,
+            deposit,1,,,,1,1.5,true,false
+            client,2,1.0,-0.5,false,,,,
+            client,3,1.0,0,false,,,,";
+        let mut atm = Atm::new();
+        atm.load_state(state.as_bytes()).unwrap();
+        assert_eq!(
+            atm.check_invariants(),
+            Err(vec![
+                Violation {
+                    client: 1,
+                    kind: ViolationKind::NegativeAvailable(Decimal::new(-1, 0)),
+                },
+                Violation {
+                    client: 1,
+                    kind: ViolationKind::HeldMismatch {
+                        held: Decimal::new(2, 0),
+                        disputed: Decimal::new(15, 1),
+                    },
+                },
+                Violation {
+                    client: 2,
+                    kind: ViolationKind::NegativeHeld(Decimal::new(-5, 1)),
+                },
+                Violation {
+                    client: 2,
+                    kind: ViolationKind::HeldMismatch {
+                        held: Decimal::new(-5, 1),
+                        disputed: Decimal::ZERO,
+                    },
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn currency_mismatch() {
+        let input = "type,client,tx,amount,currency
+            deposit,1,1,5.0,EUR
+            deposit,1,2,3.0,USD
+            deposit,1,3,1.0,
+            withdrawal,2,4,1.0,USD
+            deposit,2,5,2.0,USD
+            dispute,1,1,,USD
+            dispute,1,1,,";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![
+                (
+                    1,
+                    Decimal::ONE,
+                    Decimal::new(5, 0),
+                    Decimal::new(6, 0),
+                    false
+                ),
+                (
+                    2,
+                    Decimal::new(2, 0),
+                    Decimal::ZERO,
+                    Decimal::new(2, 0),
+                    false
+                ),
+            ]
+        );
+        assert_eq!(
+            atm.clients_iter().find(|c| c.id() == 1).unwrap().currency(),
+            Some("EUR")
+        );
+        // The mismatched deposit, the underfunded withdrawal and the mismatched dispute.
+        assert_eq!(atm.report().skipped, 3);
+        assert_eq!(
+            AtmBuilder::new()
+                .fail_fast(true)
+                .build()
+                .process_reader(input.as_bytes())
+                .unwrap_err()
+                .to_string(),
+            "Ignored tx 2 for client 1: currency mismatch"
+        );
+    }
+
+    #[test]
+    fn currency_case_insensitive() {
+        let input = "type,client,tx,amount,currency
+            deposit,1,1,5.0,usd
+            deposit,1,2,3.0,USD
+            withdrawal,1,3,1.0,Usd
+            deposit,1,4,1.0,EUR";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::new(7, 0),
+                Decimal::ZERO,
+                Decimal::new(7, 0),
+                false
+            )]
+        );
+        let client = atm.clients_iter().next().unwrap();
+        assert_eq!(client.currency(), Some("USD"));
+        assert_eq!(atm.report().skipped, 1);
+    }
+
+    #[test]
+    fn skip_footer() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            withdrawal,1,2,1.5
+            TOTAL,,,3.5";
+        let mut atm = AtmBuilder::new().footer("total").build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::new(35, 1),
+                Decimal::ZERO,
+                Decimal::new(35, 1),
+                false
+            )]
+        );
+        assert_eq!(atm.report().skipped, 0);
+
+        let trailing = format!("{}\n   \n\n", input);
+        let mut atm = AtmBuilder::new().footer("total").build();
+        atm.process_reader(trailing.as_bytes()).unwrap();
+        assert_eq!(atm.balances().len(), 1);
+
+        assert!(Atm::new().process_reader(input.as_bytes()).is_err());
+
+        let input = "type,client,tx,amount
+            TOTAL,,,5.0
+            deposit,1,1,5.0";
+        let err = AtmBuilder::new()
+            .footer("TOTAL")
+            .build()
+            .process_reader(input.as_bytes())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Footer on line 2 isn't the last row: TOTAL,,,5.0"
+        );
+    }
+
+    #[test]
+    fn state_fingerprint() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            deposit,2,2,3.0
+            withdrawal,1,3,1.5
+            dispute,1,3,
+            dispute,2,2,
+            chargeback,2,2,";
+        let run = |policy| {
+            let mut atm = AtmBuilder::new().dispute_policy(policy).build();
+            atm.process_reader(input.as_bytes()).unwrap();
+            atm.state_fingerprint()
+        };
+        assert_eq!(run(DisputePolicy::All), run(DisputePolicy::All));
+        assert_ne!(run(DisputePolicy::All), run(DisputePolicy::DepositsOnly));
+        assert_ne!(run(DisputePolicy::All), Atm::new().state_fingerprint());
+    }
+
+    #[test]
+    fn validate() {
+        let file = File::open("test_files/validate.csv").unwrap();
+        let atm = Atm::new();
+        let report = atm.validate(file).unwrap();
+        assert_eq!(
+            report.warnings().collect::<Vec<_>>(),
+            vec![
+                (3, IgnoreReason::DuplicateTx),
+                (4, IgnoreReason::InsufficientFunds),
+                (5, IgnoreReason::UnknownTx),
+                (11, IgnoreReason::UnknownTx),
+            ]
+        );
+        assert_eq!(
+            report.errors().map(|(line, _)| line).collect::<Vec<_>>(),
+            vec![6, 7, 8]
+        );
+        assert_eq!(
+            report.diagnostics[&8],
+            Diagnostic::Error(
+                "Expected 4 columns but found 5 on line 8: deposit,2,5,1.0,extra".to_string()
+            )
+        );
+        assert!(!report.is_clean());
+        assert!(atm.balances().is_empty());
+        assert_eq!(atm.report(), &ProcessingReport::default());
+    }
+
+    #[test]
+    fn overflowing_amounts() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1234567890123456789012345678901234567890
+            deposit,1,2,79228162514264337593543950335
+            deposit,1,3,1
+            deposit,2,4,2.5
+            withdrawal,2,5,1";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![
+                (1, Decimal::MAX, Decimal::ZERO, Decimal::MAX, false),
+                (
+                    2,
+                    Decimal::new(15, 1),
+                    Decimal::ZERO,
+                    Decimal::new(15, 1),
+                    false
+                ),
+            ]
+        );
+        assert_eq!(atm.report().processed, 3);
+        assert_eq!(atm.report().skipped, 2);
+
+        let err = AtmBuilder::new()
+            .strict(true)
+            .build()
+            .process_reader(input.as_bytes())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Amount out of range on line 2: deposit,1,1,1234567890123456789012345678901234567890"
+        );
+    }
+
+    #[test]
+    fn total_held() {
+        let input = "type,client,tx,amount
+            deposit,1,1,5.0
+            deposit,1,2,1.5
+            deposit,2,3,2.25
+            withdrawal,3,4,1.0
+            deposit,3,5,3.0
+            withdrawal,3,6,1.0
+            dispute,1,1,
+            dispute,2,3,
+            dispute,3,6,
+            dispute,1,2,
+            resolve,1,2,";
+        let mut atm = Atm::new();
+        assert_eq!(atm.total_held(), Some(Decimal::ZERO));
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.total_held(), Some(Decimal::new(825, 2)));
+    }
+
+    #[test]
+    fn buckets() {
+        let input = "type,client,tx,amount
+            deposit,1,1,50.0
+            deposit,2,2,100.5
+            deposit,3,3,1.0
+            withdrawal,3,4,1.0
+            deposit,4,5,100";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        let options = OutputOptions {
+            buckets: vec![Decimal::ZERO, Decimal::new(100, 0)],
+            ..OutputOptions::default()
+        };
+        let output = atm.to_csv_string_with(&options).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            vec![
+                "1,50,0,50,false,0-100",
+                "2,100.5,0,100.5,false,>100",
+                "3,0,0,0,false,<=0",
+                "4,100,0,100,false,0-100",
+                "client,available,held,total,locked,bucket",
+            ]
+        );
+    }
+
+    #[test]
+    fn checksum_footer() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.5
+            deposit,2,2,2.0
+            deposit,3,3,1.0";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        let options = OutputOptions {
+            checksum: true,
+            ..OutputOptions::default()
+        };
+        let output = atm.to_csv_string_with(&options).unwrap();
+
+        let (rows, footer) = output.trim_end().rsplit_once('\n').unwrap();
+        let rows = format!("{}\n", rows);
+        assert_eq!(
+            footer,
+            format!(
+                "# clients=3 sha256={}",
+                crate::sha256::digest(rows.as_bytes())
+            )
+        );
+        assert_eq!(rows, atm.to_csv_string().unwrap());
+
+        let mut streamed = Vec::new();
+        Atm::new()
+            .stream_sorted(input.as_bytes(), &mut streamed, &options)
+            .unwrap();
+        let streamed = String::from_utf8(streamed).unwrap();
+        let (rows, footer) = streamed.trim_end().rsplit_once('\n').unwrap();
+        let rows = format!("{}\n", rows);
+        assert_eq!(
+            footer,
+            format!(
+                "# clients=3 sha256={}",
+                crate::sha256::digest(rows.as_bytes())
+            )
+        );
+        assert_eq!(rows, atm.to_csv_string_sorted());
+    }
+
+    #[test]
+    fn sequences() {
+        let input = "type,client,tx,amount,seq
+            deposit,1,1,10.0,1
+            deposit,2,2,5.0,1
+            withdrawal,1,3,1.0,2
+            withdrawal,1,4,1.0,2
+            deposit,2,5,1.0,2
+            withdrawal,1,6,1.0,3";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().skipped, 1);
+        assert_eq!(
+            atm.to_csv_string_sorted(),
+            "client,available,held,total,locked\n1,8,0,8,false\n2,6,0,6,false\n"
+        );
+
+        let mut atm = AtmBuilder::new().strict(true).build();
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Sequence 2 of client 1 isn't after 2 on line 5: withdrawal,1,4,1.0,2"
+        );
+
+        let input = "type,client,tx,amount,seq
+            deposit,1,1,10.0,1
+            deposit,1,2,5.0,3";
+        let mut atm = AtmBuilder::new().strict(true).build();
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Sequence of client 1 skips from 1 to 3 on line 3"
+        );
+        Atm::new().process_reader(input.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn simulate() {
+        let input = "type,client,tx,amount
+            deposit,1,1,10.0
+            deposit,2,2,5.0";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        let before = atm.to_csv_string_sorted();
+        let fingerprint = atm.state_fingerprint();
+
+        let withdrawal = |client, tx, amount| Transaction {
+            client,
+            tx,
+            variant: TransactionVariant::Withdrawal {
+                amount: Decimal::new(amount, 0),
+            },
+            currency: None,
+        };
+        let snapshots = atm
+            .simulate(&[
+                withdrawal(1, 3, 4),
+                withdrawal(3, 4, 1),
+                withdrawal(1, 5, 1),
+            ])
+            .unwrap();
+        let balances: Vec<(u32, Decimal, Decimal)> = snapshots
+            .iter()
+            .map(|snapshot| (snapshot.client, snapshot.available, snapshot.held))
+            .collect();
+        assert_eq!(
+            balances,
+            vec![
+                (1, Decimal::new(5, 0), Decimal::ZERO),
+                (3, Decimal::ZERO, Decimal::ZERO),
+            ]
+        );
+
+        assert_eq!(atm.to_csv_string_sorted(), before);
+        assert_eq!(atm.state_fingerprint(), fingerprint);
+        assert_eq!(atm.report().processed, 2);
+        // The simulated tx ids are still free.
+        atm.execute(withdrawal(1, 3, 4)).unwrap();
+        assert_eq!(atm.report().skipped, 0);
+    }
+
+    #[test]
+    fn apply_many() {
+        let transaction = |client, tx, variant| Transaction {
+            client,
+            tx,
+            variant,
+            currency: None,
+        };
+        let deposit = |amount| TransactionVariant::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        let withdrawal = |amount| TransactionVariant::Withdrawal {
+            amount: Decimal::new(amount, 0),
+        };
+        let mut atm = AtmBuilder::new().fail_fast(true).build();
+        let errors = atm.apply_many(vec![
+            transaction(1, 1, deposit(10)),
+            transaction(1, 2, withdrawal(20)),
+            transaction(2, 3, deposit(5)),
+            transaction(2, 3, deposit(5)),
+            transaction(1, 1, TransactionVariant::Dispute),
+            transaction(2, 4, TransactionVariant::Resolve),
+            transaction(1, 5, withdrawal(1)),
+        ]);
+
+        let errors: Vec<(usize, IgnoreReason)> = errors
+            .into_iter()
+            .map(|(i, err)| match err {
+                AtmError::Ignored(reason) => (i, reason),
+                AtmError::Failed(err) => panic!("unexpected failure {}", err),
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (1, IgnoreReason::InsufficientFunds),
+                (3, IgnoreReason::DuplicateTx),
+                (5, IgnoreReason::UnknownTx),
+                (6, IgnoreReason::InsufficientFunds),
+            ]
+        );
+        assert_eq!(atm.report().processed, 3);
+        assert_eq!(atm.report().skipped, 4);
+        assert_eq!(
+            atm.to_csv_string_sorted(),
+            "client,available,held,total,locked\n1,0,10,10,false\n2,5,0,5,false\n"
+        );
+    }
+
+    #[test]
+    fn empty_input() {
+        let err = Atm::new().process_reader(&b""[..]).unwrap_err();
+        assert_eq!(err.to_string(), "Empty input");
+
+        let mut atm = AtmBuilder::new().allow_empty_input(true).build();
+        atm.process_reader(&b""[..]).unwrap();
+        assert_eq!(atm.report(), &ProcessingReport::default());
+        assert_eq!(
+            atm.to_csv_string().unwrap(),
+            "client,available,held,total,locked\n"
+        );
+    }
+
+    #[test]
+    fn without_headers() {
+        let data = "deposit,1,1,10.0
+            withdrawal,1,2,1.5";
+        let mut atm = AtmBuilder::new().has_headers(false).build();
+        atm.process_reader(data.as_bytes()).unwrap();
+        assert_eq!(atm.report().skipped, 0);
+        let expected = atm.to_csv_string().unwrap();
+
+        let input = format!("type,client,tx,amount\n{}", data);
+        let mut atm = AtmBuilder::new().has_headers(false).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.report().processed, 2);
+        assert_eq!(atm.report().skipped, 1);
+        assert_eq!(atm.to_csv_string().unwrap(), expected);
+        assert_eq!(
+            expected,
+            "client,available,held,total,locked\n1,8.5,0,8.5,false\n"
+        );
+
+        // Only the first row can be a header.
+        let input = format!("{}\ntype,client,tx,amount", data);
+        let mut atm = AtmBuilder::new().has_headers(false).build();
+        assert!(atm.process_reader(input.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn event_log() {
+        let input = "type,client,tx,amount
+            deposit,1,1,10.0
+            dispute,1,1,
+            withdrawal,1,2,1.0
+            chargeback,1,1,";
+        let mut atm = AtmBuilder::new().event_log(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let balances = |available, held| Balances {
+            available: Decimal::new(available, 0),
+            held: Decimal::new(held, 0),
+        };
+        let transaction = |tx, variant| Transaction {
+            client: 1,
+            tx,
+            variant,
+            currency: None,
+        };
+        assert_eq!(
+            atm.events(),
+            &[
+                LedgerEvent::Applied {
+                    transaction: transaction(
+                        1,
+                        TransactionVariant::Deposit {
+                            amount: Decimal::new(10, 0)
+                        }
+                    ),
+                    before: balances(0, 0),
+                    after: balances(10, 0),
+                    locked: false,
+                },
+                LedgerEvent::Applied {
+                    transaction: transaction(1, TransactionVariant::Dispute),
+                    before: balances(10, 0),
+                    after: balances(0, 10),
+                    locked: false,
+                },
+                LedgerEvent::Skipped {
+                    transaction: transaction(
+                        2,
+                        TransactionVariant::Withdrawal {
+                            amount: Decimal::new(1, 0)
+                        }
+                    ),
+                    reason: IgnoreReason::InsufficientFunds,
+                },
+                LedgerEvent::Applied {
+                    transaction: transaction(1, TransactionVariant::Chargeback),
+                    before: balances(0, 10),
+                    after: balances(0, 0),
+                    locked: true,
+                },
+            ]
+        );
+        assert!(Atm::new().events().is_empty());
+    }
+
+    #[test]
+    fn event_log_csv() {
+        let input = "type,client,tx,amount
+            deposit,1,1,10.0
+            withdrawal,1,2,11.0";
+        let mut atm = AtmBuilder::new().event_log(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        // The variants have different fields, so the rows have different lengths.
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_writer(vec![]);
+        for event in atm.events() {
+            writer.serialize(event).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "applied,deposit,1,1,10,0,0,10,0,false\n\
+             skipped,withdrawal,1,2,11,insufficient funds\n"
+        );
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(output.as_bytes());
+        let records: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        let transaction = |record: &csv::StringRecord| -> Transaction {
+            let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+            let fields: Vec<&str> = record.iter().skip(1).take(4).collect();
+            csv::StringRecord::from(fields)
+                .deserialize(Some(&headers))
+                .unwrap()
+        };
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].iter().collect::<Vec<_>>(),
+            vec!["applied", "deposit", "1", "1", "10", "0", "0", "10", "0", "false"]
+        );
+        assert_eq!(&records[1][0], "skipped");
+        assert_eq!(&records[1][5], IgnoreReason::InsufficientFunds.to_string());
+        let transactions: Vec<Transaction> = records.iter().map(transaction).collect();
+        let expected: Vec<Transaction> = atm
+            .events()
+            .iter()
+            .map(|event| match event {
+                LedgerEvent::Applied { transaction, .. } => transaction.clone(),
+                LedgerEvent::Skipped { transaction, .. } => transaction.clone(),
+            })
+            .collect();
+        assert_eq!(transactions, expected);
+    }
+
+    #[test]
+    fn significant_figures() {
+        let input = "type,client,tx,amount
+            deposit,1,1,123.456
+            deposit,1,2,10
+            dispute,1,2,";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let options = |figures| OutputOptions {
+            significant_figures: Some(figures),
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            atm.to_csv_string_with(&options(4)).unwrap(),
+            "client,available,held,total,locked\n1,1.235e2,1.000e1,1.335e2,false\n"
+        );
+        assert_eq!(
+            atm.to_csv_string_with(&options(3)).unwrap(),
+            "client,available,held,total,locked\n1,123,1.00e1,133,false\n"
+        );
+    }
+
+    #[test]
+    fn fully_disputed() {
+        let input = "type,client,tx,amount
+            deposit,1,1,2.0
+            dispute,1,1,
+            deposit,2,2,2.0
+            deposit,2,3,1.0
+            dispute,2,3,
+            deposit,3,4,1.0
+            withdrawal,3,5,1.0";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        let options = OutputOptions {
+            fully_disputed: true,
+            ..OutputOptions::default()
+        };
+        let output = atm.to_csv_string_with(&options).unwrap();
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort_unstable();
+        assert_eq!(
+            lines,
+            vec![
+                "1,0,2,2,false,true",
+                "2,2,1,3,false,false",
+                "3,0,0,0,false,false",
+                "client,available,held,total,locked,fully_disputed",
+            ]
+        );
+    }
+
+    #[test]
+    fn sums() {
+        let input = "type,client,tx,amount
+            deposit,1,1,10.0
+            deposit,1,2,2.5
+            withdrawal,1,3,4.0
+            withdrawal,1,4,100.0
+            dispute,1,2,
+            chargeback,1,2,
+            dispute,1,9,";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        let options = OutputOptions {
+            sums: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            atm.to_csv_string_with(&options).unwrap(),
+            "client,available,held,total,locked,\
+             total_deposited,total_withdrawn,total_disputed,total_charged_back\n\
+             1,6.0,0.0,6.0,true,12.5,4,2.5,2.5\n"
+        );
+    }
+
+    #[test]
+    fn uppercase_header() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.5";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let options = OutputOptions {
+            uppercase_header: true,
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            atm.to_csv_string_with(&options).unwrap(),
+            "CLIENT,AVAILABLE,HELD,TOTAL,LOCKED\n1,1.5,0,1.5,false\n"
+        );
+
+        let options = OutputOptions {
+            extended: true,
+            ..OutputOptions::default()
+        };
+        let lower = atm.to_csv_string_with(&options).unwrap();
+        let options = OutputOptions {
+            uppercase_header: true,
+            ..options
+        };
+        let upper = atm.to_csv_string_with(&options).unwrap();
+        let (lower_header, lower_rows) = lower.split_once('\n').unwrap();
+        let (upper_header, upper_rows) = upper.split_once('\n').unwrap();
+        assert_eq!(upper_header, lower_header.to_uppercase());
+        assert_eq!(upper_rows, lower_rows);
+    }
+
+    #[test]
+    fn control_only_clients() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            dispute,1,1,
+            dispute,2,1,
+            resolve,2,1,
+            withdrawal,3,2,1.0";
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.control_only_clients(), vec![2]);
+    }
+
+    #[test]
+    fn skip_balances_without_txs() {
+        let checkpoint = "record,client,available,held,locked,tx,amount,disputed,reversed
+            client,1,2.0,0,false,,,,
+            client,2,1.0,0,false,,,,
+            deposit,2,,,,1,1.0,false,false
+            client,3,0,0,false,,,,";
+        let mut atm = Atm::new();
+        atm.load_state(checkpoint.as_bytes()).unwrap();
+
+        let options = OutputOptions {
+            skip_balances_without_txs: true,
+            ..OutputOptions::default()
+        };
+        let csv = atm.to_csv_string_with(&options).unwrap();
+        let mut rows: Vec<&str> = csv.lines().collect();
+        rows.sort_unstable();
+        assert_eq!(
+            rows,
+            vec![
+                "2,1,0,1,false",
+                "3,0,0,0,false",
+                "client,available,held,total,locked"
+            ]
+        );
+        assert_eq!(atm.to_csv_string().unwrap().lines().count(), 4);
+    }
+
+    #[test]
+    fn unlock_after_chargeback() {
+        let mut atm = Atm::with_config(Config {
+            reject_locked: true,
+            ..Config::default()
+        });
+        let input = "type,client,tx,amount
+            deposit,1,1,2.0
+            deposit,1,2,1.0
+            dispute,1,2,
+            chargeback,1,2,
+            deposit,1,3,5.0
+            unlock,1,4,
+            unlock,1,5,
+            deposit,1,6,3.0";
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::new(5, 0),
+                Decimal::ZERO,
+                Decimal::new(5, 0),
+                false
+            )]
+        );
+        // The deposit while locked and the second unlock.
+        assert_eq!(atm.report().skipped, 2);
+    }
+
+    #[test]
+    fn deposits_only_dispute_policy() {
+        let path = Path::new("test_files/dispute_withdrawal.in");
+        let default = AtmBuilder::new().from_path(path).unwrap();
+        assert_eq!(default.balances(), Atm::from_path(path).unwrap().balances());
+
+        let atm = AtmBuilder::new()
+            .dispute_policy(DisputePolicy::DepositsOnly)
+            .from_path(path)
+            .unwrap();
+        let balance = Decimal::new(15, 1);
+        assert_eq!(
+            atm.balances(),
+            vec![
+                (3, balance, Decimal::ZERO, balance, false),
+                (4, balance, Decimal::ZERO, balance, false),
+                (5, balance, Decimal::ZERO, balance, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn max_integer_digits() {
+        let input = "type,client,tx,amount
+            deposit,1,1,1.0
+            deposit,1,2,123456789012345678901234567890.0
+            deposit,1,3,2.0";
+        let mut atm = AtmBuilder::new().max_integer_digits(20).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(atm.balances()[0].1, Decimal::new(3, 0));
+        assert_eq!(atm.report().skipped, 1);
+
+        let mut atm = AtmBuilder::new()
+            .max_integer_digits(20)
+            .strict(true)
+            .build();
+        let err = atm.process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Amount with 30 integer digits exceeds 20 on line 3: \
+             deposit,1,2,123456789012345678901234567890.0"
+        );
+    }
+
+    #[test]
+    fn from_path_with_locks() {
+        let (atm, locked) =
+            Atm::from_path_with_locks(Path::new("test_files/dispute_deposit.in")).unwrap();
+        assert_eq!(locked, vec![2]);
+        assert_eq!(atm.balances().len(), 3);
+    }
+
+    #[test]
+    fn write_csv_to_path_matches_string() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for i in 0..u16::MAX as u32 {
+            input.push_str(&format!("deposit,{},{},{}.{}\n", i, i, i, i % 10000));
+            if i % 7 == 0 {
+                input.push_str(&format!("dispute,{},{},\n", i, i));
+            }
+        }
+        let mut atm = Atm::new();
+        atm.process_reader(input.as_bytes()).unwrap();
+
+        let options = OutputOptions {
+            extended: true,
+            ..OutputOptions::default()
+        };
+        let path = std::env::temp_dir().join(format!("rt-test-{}.csv", std::process::id()));
+        atm.write_csv_to_path(&path, &options).unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, atm.to_csv_string_with(&options).unwrap());
+    }
+
+    #[cfg(feature = "unicode-digits")]
+    #[test]
+    fn normalize_unicode_digits() {
+        let input = "type,client,tx,amount\ndeposit,\u{661},\u{662},\u{663}.\u{665}\n";
+        let mut atm = Atm::new();
+        assert!(atm.process_reader(input.as_bytes()).is_err());
+
+        let mut atm = AtmBuilder::new().normalize_digits(true).build();
+        atm.process_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            atm.balances(),
+            vec![(
+                1,
+                Decimal::new(35, 1),
+                Decimal::ZERO,
+                Decimal::new(35, 1),
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn transaction_round_trip() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.5
+withdrawal,1,2,0.25
+dispute,1,1,
+resolve,1,1,
+chargeback,1,2,
+correction,2,3,
+unlock,1,4,
+";
+        let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+        let transactions: Vec<Transaction> = reader.deserialize().map(|row| row.unwrap()).collect();
+        let mut writer = Writer::from_writer(vec![]);
+        for transaction in &transactions {
+            writer.serialize(transaction).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output, input);
+
+        let mut reader = ReaderBuilder::new().from_reader(output.as_bytes());
+        let read_back: Vec<Transaction> = reader.deserialize().map(|row| row.unwrap()).collect();
+        assert_eq!(read_back, transactions);
+    }
+
+    #[test]
+    fn transaction_round_trip_with_currency() {
+        let input = "type,client,tx,amount,currency
+deposit,1,1,1.5,EUR
+withdrawal,1,2,0.25,
+dispute,1,1,,EUR
+resolve,1,1,,
+chargeback,1,2,,
+correction,2,3,,
+unlock,1,4,,
+";
+        let mut reader = ReaderBuilder::new().from_reader(input.as_bytes());
+        let transactions: Vec<Transaction> = reader.deserialize().map(|row| row.unwrap()).collect();
+        let mut writer = Writer::from_writer(vec![]);
+        for transaction in &transactions {
+            writer.serialize(transaction.with_currency()).unwrap();
+        }
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output, input);
+
+        let mut reader = ReaderBuilder::new().from_reader(output.as_bytes());
+        let read_back: Vec<Transaction> = reader.deserialize().map(|row| row.unwrap()).collect();
+        assert_eq!(read_back, transactions);
+    }
+
+    #[test]
+    fn unknown_type_reports_line() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nrefund,1,2,1.0\n";
+        let err = Atm::new().process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse transaction on line 3: refund,1,2,1.0"
+        );
+        assert!(format!("{:#}", err).contains("unknown variant `refund`"));
+    }
+
+    #[test]
+    fn truncated_row_reports_line() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit, 1, 2\n";
+        let err = Atm::new().process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to parse transaction on line 3: deposit,1,2"
+        );
+        assert!(format!("{:#}", err).contains("end of row"));
+    }
+
+    #[test]
+    fn extra_column_reports_line() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0,oops\n";
+        let err = Atm::new().process_reader(input.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected 4 columns but found 5 on line 3: deposit,1,2,1.0,oops"
+        );
+    }
+
+    #[test]
+    fn strict_header_validation() {
+        let mut atm = Atm::new();
+        let err = atm
+            .process_reader("type,tx,amount\n".as_bytes())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Missing column 'client' in header type,tx,amount"
+        );
+
+        let mut atm = Atm::with_config(Config {
+            strict: true,
+            ..Config::default()
+        });
+        let err = atm
+            .process_reader("type,client,tx,amount,note\n".as_bytes())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown column 'note' in header type,client,tx,amount,note"
+        );
+        let err = atm
+            .process_reader("type,client,tx,amount\ndispute,1,1\n".as_bytes())
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Expected 4 columns but found 3 on line 2: dispute,1,1"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use std::path::Path;
+
+use crate::atm::Atm;
+use crate::config::{Aliases, Config, DisputePolicy, EofDisputePolicy};
+
+/// Builds an atm with a custom configuration.
+///
+/// The defaults reproduce the behavior of `Atm::new()`.
+///
+/// ```
+/// use rt_test::builder::AtmBuilder;
+/// use rt_test::config::DisputePolicy;
+/// use rust_decimal::Decimal;
+///
+/// let mut atm = AtmBuilder::new()
+///     .precision(2)
+///     .overdraft_limit(Decimal::new(100, 0))
+///     .dispute_policy(DisputePolicy::DepositsOnly)
+///     .build();
+/// atm.process_reader("type,client,tx,amount\nwithdrawal,1,1,10.555\n".as_bytes())?;
+/// assert_eq!(atm.balances()[0].1, Decimal::new(-1056, 2));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct AtmBuilder {
+    config: Config,
+}
+
+impl AtmBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Aliases for header and transaction type names.
+    pub fn aliases(mut self, aliases: Aliases) -> Self {
+        self.config.aliases = aliases;
+        self
+    }
+
+    /// The number of decimal places amounts are rounded to.
+    pub fn precision(mut self, precision: u32) -> Self {
+        self.config.precision = precision;
+        self
+    }
+
+    /// How far below zero the available funds of a client may go.
+    pub fn overdraft_limit(mut self, limit: Decimal) -> Self {
+        self.config.overdraft_limit = limit;
+        self
+    }
+
+    /// Which transactions can be disputed.
+    pub fn dispute_policy(mut self, policy: DisputePolicy) -> Self {
+        self.config.dispute_policy = policy;
+        self
+    }
+
+    /// Treat malformed rows as errors.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Fail on the first transaction that would be ignored.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.config.fail_fast = fail_fast;
+        self
+    }
+
+    /// The maximum number of digits in the integer part of an amount.
+    pub fn max_integer_digits(mut self, digits: usize) -> Self {
+        self.config.max_integer_digits = Some(digits);
+        self
+    }
+
+    /// Treat amounts without a decimal point as malformed.
+    pub fn require_decimal_point(mut self, require: bool) -> Self {
+        self.config.require_decimal_point = require;
+        self
+    }
+
+    /// Log ignored transactions to stderr.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.config.verbose = verbose;
+        self
+    }
+
+    /// Fail if a client's held funds ever exceed the sum of their deposits.
+    pub fn check_held_invariant(mut self, check: bool) -> Self {
+        self.config.check_held_invariant = check;
+        self
+    }
+
+    /// Ignore deposits and withdrawals for locked clients.
+    pub fn reject_locked(mut self, reject: bool) -> Self {
+        self.config.reject_locked = reject;
+        self
+    }
+
+    /// Route the remainders of rounded deposits to a per client rounding account.
+    pub fn rounding_account(mut self, enabled: bool) -> Self {
+        self.config.rounding_account = enabled;
+        self
+    }
+
+    /// Read the first row of the input as a header.
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.config.has_headers = has_headers;
+        self
+    }
+
+    /// Accept a zero-byte input as an input without transactions, instead of failing.
+    pub fn allow_empty_input(mut self, allow: bool) -> Self {
+        self.config.allow_empty_input = allow;
+        self
+    }
+
+    /// The field delimiter of the input.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.config.delimiter = delimiter;
+        self
+    }
+
+    /// The quote character of the input.
+    pub fn quote(mut self, quote: u8) -> Self {
+        self.config.quote = quote;
+        self
+    }
+
+    /// The number of rows buffered between reading and processing.
+    pub fn channel_capacity(mut self, capacity: usize) -> Self {
+        self.config.channel_capacity = capacity;
+        self
+    }
+
+    /// The number of threads clients are processed on, the result doesn't depend on it.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.workers = workers;
+        self
+    }
+
+    /// Report txs left disputed at the end of the input.
+    pub fn report_unresolved_disputes(mut self, report: bool) -> Self {
+        self.config.report_unresolved_disputes = report;
+        self
+    }
+
+    /// What happens to txs left disputed at the end of the input.
+    pub fn eof_disputes(mut self, policy: EofDisputePolicy) -> Self {
+        self.config.eof_disputes = policy;
+        self
+    }
+
+    /// Record why transactions were ignored.
+    pub fn record_ignored(mut self, record: bool) -> Self {
+        self.config.record_ignored = record;
+        self
+    }
+
+    /// Log every applied and ignored transaction.
+    pub fn event_log(mut self, enabled: bool) -> Self {
+        self.config.event_log = enabled;
+        self
+    }
+
+    /// Skip a trailing footer row with 'sentinel' in the type column.
+    pub fn footer(mut self, sentinel: &str) -> Self {
+        self.config.footer = Some(sentinel.to_string());
+        self
+    }
+
+    /// Convert non-ASCII decimal digits to ASCII before parsing.
+    #[cfg(feature = "unicode-digits")]
+    pub fn normalize_digits(mut self, normalize: bool) -> Self {
+        self.config.normalize_digits = normalize;
+        self
+    }
+
+    /// The configuration built so far.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn build(self) -> Atm {
+        Atm::with_config(self.config)
+    }
+
+    /// Build the atm and process transactions from the csv file specified by 'path'.
+    pub fn from_path(self, path: &Path) -> Result<Atm> {
+        let mut atm = self.build();
+        atm.process_path(path)?;
+        atm.finish()?;
+        Ok(atm)
+    }
+}
@@ -1,24 +1,128 @@
 use anyhow::bail;
-use anyhow::Result;
-use rust_decimal::Decimal;
-use serde::{Serialize, Serializer};
+use anyhow::{anyhow, Result};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
 use crate::atm::{Transaction, TransactionVariant};
+use crate::config::{Config, DisputePolicy, LockedFormat, OutputOptions};
+use crate::invariant::ViolationKind;
+use crate::state::{RecordKind, StateRow};
 
 /// Tx amount, used to avoid mixing deposits/withdrawals.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Hash)]
 enum TxAmount {
     Deposit(Decimal),
     Withdrawal(Decimal),
+    /// Signed, and not disputable.
+    Adjustment(Decimal),
+}
+
+impl TxAmount {
+    fn value(&self) -> Decimal {
+        match self {
+            TxAmount::Deposit(amount)
+            | TxAmount::Withdrawal(amount)
+            | TxAmount::Adjustment(amount) => *amount,
+        }
+    }
+}
+
+/// The reason a transaction was ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    /// Not enough available funds.
+    InsufficientFunds,
+    /// The referenced tx doesn't exist for the client.
+    UnknownTx,
+    /// The referenced tx isn't disputed.
+    NotDisputed,
+    /// The referenced tx is disputed.
+    Disputed,
+    /// The referenced tx has been reversed by a correction.
+    Reversed,
+    /// The tx id has already been used.
+    DuplicateTx,
+    /// The referenced tx can't be disputed under the dispute policy.
+    NotDisputable,
+    /// The client is locked.
+    Locked,
+    /// The client isn't locked.
+    NotLocked,
+    /// Applying the tx would overflow the balances.
+    Overflow,
+    /// The currency differs from the currency of the client.
+    CurrencyMismatch,
+    /// Less funds are held than the disputed tx would release.
+    InsufficientHeld,
+    /// The referenced tx has been charged back, so it can't be corrected, nor disputed
+    /// again while the client is locked.
+    ChargedBack,
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            IgnoreReason::InsufficientFunds => "insufficient funds",
+            IgnoreReason::UnknownTx => "unknown tx",
+            IgnoreReason::NotDisputed => "tx not disputed",
+            IgnoreReason::Disputed => "tx disputed",
+            IgnoreReason::Reversed => "tx reversed",
+            IgnoreReason::DuplicateTx => "duplicate tx",
+            IgnoreReason::NotDisputable => "tx not disputable",
+            IgnoreReason::Locked => "client locked",
+            IgnoreReason::NotLocked => "client not locked",
+            IgnoreReason::Overflow => "balance overflow",
+            IgnoreReason::CurrencyMismatch => "currency mismatch",
+            IgnoreReason::InsufficientHeld => "insufficient held funds",
+            IgnoreReason::ChargedBack => "tx charged back",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// The result of applying a transaction, with the reason if it was ignored.
+type Applied = std::result::Result<(), IgnoreReason>;
+
+/// Ignore releasing the funds held for a tx if it would make held funds negative, which
+/// can only happen if the held funds of a loaded state have drifted from the disputes.
+fn release(held: Decimal, tx: &Tx) -> Applied {
+    if held < tx.held {
+        return Err(IgnoreReason::InsufficientHeld);
+    }
+    Ok(())
+}
+
+/// Ignore the transaction if the checked arithmetic overflowed.
+fn checked(value: Option<Decimal>) -> std::result::Result<Decimal, IgnoreReason> {
+    value.ok_or(IgnoreReason::Overflow)
+}
+
+/// The kind of a stored transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxKind {
+    Deposit,
+    Withdrawal,
+    Adjustment,
 }
 
 /// A single transaction.
-#[derive(Debug)]
-struct Tx {
+#[derive(Debug, Clone, Hash)]
+pub struct Tx {
     id: u32,
     amount: TxAmount,
     disputed: bool,
+    reversed: bool,
+    /// Charged back, the dispute can be reopened once the client is unlocked.
+    charged_back: bool,
+    /// The funds held for the tx while it's disputed, released exactly when it's resolved
+    /// or charged back.
+    held: Decimal,
+    /// The position of the tx among the txs of the client.
+    arrival: usize,
 }
 
 impl Tx {
@@ -27,33 +131,274 @@ impl Tx {
             id,
             amount,
             disputed: false,
+            reversed: false,
+            charged_back: false,
+            held: Decimal::ZERO,
+            arrival: 0,
         }
     }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn kind(&self) -> TxKind {
+        match self.amount {
+            TxAmount::Deposit(_) => TxKind::Deposit,
+            TxAmount::Withdrawal(_) => TxKind::Withdrawal,
+            TxAmount::Adjustment(_) => TxKind::Adjustment,
+        }
+    }
+
+    /// The amount as rounded when applied, negative for a fee.
+    pub fn amount(&self) -> Decimal {
+        self.amount.value()
+    }
+
+    pub fn disputed(&self) -> bool {
+        self.disputed
+    }
+
+    /// If the tx has been reversed by a correction.
+    pub fn reversed(&self) -> bool {
+        self.reversed
+    }
 }
 
 /// An individual client.
 ///
 /// Since transactions are held by the client, they're not globally unique.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Client {
-    id: u16,
+    id: u32,
     available: Decimal,
     held: Decimal,
     locked: bool,
     txs: HashMap<u32, Tx>,
+    deposited: Decimal,
+    counts: TxCounts,
+    sums: TxSums,
+    rounding_error: Decimal,
+    rounding_account: Decimal,
+    control_only: bool,
+    currency: Option<String>,
+}
+
+/// The number of applied transactions of each type.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TxCounts {
+    pub deposits: u32,
+    pub withdrawals: u32,
+    pub disputes: u32,
+    pub resolves: u32,
+    pub chargebacks: u32,
+    pub corrections: u32,
+    pub unlocks: u32,
+    pub adjustments: u32,
+}
+
+impl TxCounts {
+    fn record(&mut self, variant: &TransactionVariant) {
+        let count = match variant {
+            TransactionVariant::Deposit { .. } => &mut self.deposits,
+            TransactionVariant::Withdrawal { .. } => &mut self.withdrawals,
+            TransactionVariant::Dispute => &mut self.disputes,
+            TransactionVariant::Resolve => &mut self.resolves,
+            TransactionVariant::Chargeback => &mut self.chargebacks,
+            TransactionVariant::Correction => &mut self.corrections,
+            TransactionVariant::Unlock => &mut self.unlocks,
+            TransactionVariant::Adjustment { .. } => &mut self.adjustments,
+        };
+        *count += 1;
+    }
+}
+
+/// The sums of the amounts of applied transactions of each type.
+///
+/// Disputes and chargebacks sum the amounts of the txs they reference.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TxSums {
+    pub deposited: Decimal,
+    pub withdrawn: Decimal,
+    pub disputed: Decimal,
+    pub charged_back: Decimal,
+}
+
+impl TxSums {
+    fn record(&mut self, variant: &TransactionVariant, amount: Decimal) {
+        let sum = match variant {
+            TransactionVariant::Deposit { .. } => &mut self.deposited,
+            TransactionVariant::Withdrawal { .. } => &mut self.withdrawn,
+            TransactionVariant::Dispute => &mut self.disputed,
+            TransactionVariant::Chargeback => &mut self.charged_back,
+            _ => return,
+        };
+        // Withdrawals let deposits add up beyond what fits, so the sums saturate.
+        *sum = sum.checked_add(amount).unwrap_or(Decimal::MAX);
+    }
 }
 
 impl Client {
-    pub fn new(id: u16) -> Self {
+    pub fn new(id: u32) -> Self {
         Self {
             id,
             available: Decimal::ZERO,
             held: Decimal::ZERO,
             locked: false,
             txs: HashMap::new(),
+            deposited: Decimal::ZERO,
+            counts: TxCounts::default(),
+            sums: TxSums::default(),
+            rounding_error: Decimal::ZERO,
+            rounding_account: Decimal::ZERO,
+            control_only: true,
+            currency: None,
         }
     }
 
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    pub fn held(&self) -> Decimal {
+        self.held
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// If the client holds any deposits or withdrawals.
+    pub fn has_txs(&self) -> bool {
+        !self.txs.is_empty()
+    }
+
+    /// The stored txs of 'kind' in the order they were applied.
+    pub fn transactions_of(&self, kind: TxKind) -> Vec<&Tx> {
+        let mut txs: Vec<&Tx> = self.txs.values().filter(|tx| tx.kind() == kind).collect();
+        txs.sort_by_key(|tx| tx.arrival);
+        txs
+    }
+
+    /// The number of applied transactions of each type.
+    pub fn counts(&self) -> &TxCounts {
+        &self.counts
+    }
+
+    /// The sums of the amounts of applied transactions of each type.
+    pub fn sums(&self) -> &TxSums {
+        &self.sums
+    }
+
+    /// The sum of the amounts dropped when rounding applied deposits and withdrawals.
+    pub fn accumulated_rounding_error(&self) -> Decimal {
+        self.rounding_error
+    }
+
+    /// The remainders of rounded deposits, if routed to the rounding account.
+    ///
+    /// Remainders are signed, deposits rounded up leave a negative remainder, so the account
+    /// may be negative. Each remainder is at most half a unit of the configured precision.
+    /// The rounding account isn't part of the available, held or total funds.
+    pub fn rounding_account(&self) -> Decimal {
+        self.rounding_account
+    }
+
+    /// If the client has only been referenced by control transactions,
+    /// like disputes, and never by a deposit or withdrawal.
+    pub fn control_only(&self) -> bool {
+        self.control_only
+    }
+
+    /// The sum of the funds held for the currently disputed txs, which is the held funds
+    /// unless a loaded state has drifted.
+    pub fn disputed(&self) -> Decimal {
+        self.txs.values().map(|tx| tx.held).sum()
+    }
+
+    /// If all funds are disputed, with no available funds but held funds.
+    pub fn fully_disputed(&self) -> bool {
+        self.available.is_zero() && self.held > Decimal::ZERO
+    }
+
+    /// The ids of the currently disputed txs, sorted by id.
+    pub fn disputed_txs(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self
+            .txs
+            .values()
+            .filter(|tx| tx.disputed)
+            .map(|tx| tx.id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The currency set by the first transaction with a currency.
+    pub fn currency(&self) -> Option<&str> {
+        self.currency.as_deref()
+    }
+
+    /// Check the invariants of the client, returning every one that's broken.
+    pub(crate) fn violations(&self, config: &Config) -> Vec<ViolationKind> {
+        let mut violations = Vec::new();
+        if self.available < -config.overdraft_limit {
+            violations.push(ViolationKind::NegativeAvailable(self.available));
+        }
+        if self.held < Decimal::ZERO {
+            violations.push(ViolationKind::NegativeHeld(self.held));
+        }
+        if self.available.checked_add(self.held).is_none() {
+            violations.push(ViolationKind::TotalMismatch);
+        }
+        let disputed = self
+            .txs
+            .values()
+            .try_fold(Decimal::ZERO, |sum, tx| sum.checked_add(tx.held));
+        match disputed {
+            Some(disputed) if disputed == self.held => {}
+            Some(disputed) => violations.push(ViolationKind::HeldMismatch {
+                held: self.held,
+                disputed,
+            }),
+            None => violations.push(ViolationKind::TotalMismatch),
+        }
+        violations
+    }
+
+    /// Check that the balances don't have more decimal places than 'precision'.
+    pub(crate) fn precision_violations(&self, precision: u32) -> Vec<ViolationKind> {
+        let mut violations = Vec::new();
+        if self.available.normalize().scale() > precision {
+            violations.push(ViolationKind::AvailablePrecision(self.available));
+        }
+        if self.held.normalize().scale() > precision {
+            violations.push(ViolationKind::HeldPrecision(self.held));
+        }
+        violations
+    }
+
+    /// Feed the full state of the client into 'state', with txs in a deterministic order.
+    pub(crate) fn hash_state<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+        self.available.hash(state);
+        self.held.hash(state);
+        self.locked.hash(state);
+        let mut txs: Vec<&Tx> = self.txs.values().collect();
+        txs.sort_by_key(|tx| tx.id);
+        txs.hash(state);
+        self.deposited.hash(state);
+        self.counts.hash(state);
+        self.sums.hash(state);
+        self.rounding_error.hash(state);
+        self.rounding_account.hash(state);
+        self.control_only.hash(state);
+        self.currency.hash(state);
+    }
+
     /// The total amount of a client.
     /// Implemented as a method instead of a field to ensure that it's always equal to available + held.
     pub fn total(&self) -> Decimal {
@@ -61,119 +406,370 @@ impl Client {
     }
 
     /// Execute a transaction and update client state.
-    pub fn execute(&mut self, t: Transaction) -> Result<()> {
-        match t.variant {
-            TransactionVariant::Deposit { amount } => {
-                self.deposit(t.tx, amount);
-            }
-            TransactionVariant::Withdrawal { amount } => {
-                self.withdrawal(t.tx, amount);
+    ///
+    /// Faulty transactions are ignored, returning the reason why.
+    pub fn execute(&mut self, t: Transaction, config: &Config) -> Result<Option<IgnoreReason>> {
+        if t.variant.has_amount() {
+            self.control_only = false;
+        }
+        // Currency codes are case-insensitive, so `usd` and `USD` are the same currency.
+        let currency = t.currency.map(|currency| currency.to_uppercase());
+        let mismatch = match (&self.currency, &currency) {
+            (Some(currency), Some(other)) => currency != other,
+            _ => false,
+        };
+        let applied = match t.variant {
+            _ if mismatch => Err(IgnoreReason::CurrencyMismatch),
+            // Let disputes on a locked account play out, but stop new funds from moving.
+            _ if config.reject_locked && self.locked && t.variant.has_amount() => {
+                Err(IgnoreReason::Locked)
             }
+            TransactionVariant::Deposit { amount } => self.deposit(t.tx, amount, config),
+            TransactionVariant::Withdrawal { amount } => self.withdrawal(t.tx, amount, config),
             TransactionVariant::Dispute => {
-                self.dispute(t.tx);
-            }
-            TransactionVariant::Resolve => {
-                self.resolve(t.tx);
+                self.dispute(t.tx, config.dispute_policy, config.overdraft_limit)
             }
-            TransactionVariant::Chargeback => {
-                self.chargeback(t.tx);
+            TransactionVariant::Resolve => self.resolve(t.tx),
+            TransactionVariant::Chargeback => self.chargeback(t.tx),
+            TransactionVariant::Correction => self.correction(t.tx, config.overdraft_limit),
+            TransactionVariant::Unlock => self.unlock(),
+            TransactionVariant::Adjustment { amount } => self.adjustment(t.tx, amount, config),
+        };
+        if applied.is_ok() {
+            self.counts.record(&t.variant);
+            let amount = self.txs.get(&t.tx).map_or(Decimal::ZERO, Tx::amount);
+            self.sums.record(&t.variant, amount);
+            if self.currency.is_none() {
+                self.currency = currency;
             }
         }
 
         // If these sanity checks screw up, something very serious has gone wrong
         // and we should call the fire department.
         // A better solution might be to enforce this via an unsigned Decimal type.
-        if self.available < Decimal::ZERO {
-            bail!("Failed available non-zero sanity check {:#?}", self);
+        if self.available < -config.overdraft_limit {
+            bail!("Failed available within overdraft sanity check {:#?}", self);
         }
         if self.held < Decimal::ZERO {
             bail!("Failed held non-zero sanity check {:#?}", self);
         }
+        if config.check_held_invariant && self.held > self.deposited {
+            bail!("Failed held within deposits invariant check {:#?}", self);
+        }
+
+        Ok(applied.err())
+    }
 
+    fn deposit(&mut self, tx: u32, amount: Decimal, config: &Config) -> Applied {
+        // Only consider the configured decimal points, 4 by default
+        let rounded = amount.round_dp(config.precision);
+        let available = checked(self.available.checked_add(rounded))?;
+        let deposited = checked(self.deposited.checked_add(rounded))?;
+        checked(available.checked_add(self.held))?;
+        self.rounding_error += amount - rounded;
+        if config.rounding_account {
+            self.rounding_account += amount - rounded;
+        }
+        self.available = available;
+        self.deposited = deposited;
+        self.insert_tx(Tx::new(tx, TxAmount::Deposit(rounded)));
         Ok(())
     }
 
-    fn deposit(&mut self, tx: u32, amount: Decimal) {
-        // Only consider the 4 decimal points
-        let amount = amount.round_dp(4);
-        self.available += amount;
-        self.insert_tx(Tx::new(tx, TxAmount::Deposit(amount)));
+    fn withdrawal(&mut self, tx: u32, amount: Decimal, config: &Config) -> Applied {
+        // Only consider the configured decimal points, 4 by default
+        let rounded = amount.round_dp(config.precision);
+        let available = checked(self.available.checked_sub(rounded))?;
+        // A withdrawal without enough funds should be ignored.
+        if available < -config.overdraft_limit {
+            return Err(IgnoreReason::InsufficientFunds);
+        }
+        self.rounding_error += amount - rounded;
+        self.available = available;
+        self.insert_tx(Tx::new(tx, TxAmount::Withdrawal(rounded)));
+        Ok(())
     }
 
-    fn withdrawal(&mut self, tx: u32, amount: Decimal) {
-        // Only consider the 4 decimal points
-        let amount = amount.round_dp(4);
-        // A withdrawal without enough funds should be silently ignored.
-        if amount <= self.available {
-            self.available -= amount;
-            self.insert_tx(Tx::new(tx, TxAmount::Withdrawal(amount)));
+    fn adjustment(&mut self, tx: u32, amount: Decimal, config: &Config) -> Applied {
+        // Only consider the configured decimal points, 4 by default
+        let rounded = amount.round_dp(config.precision);
+        let available = checked(self.available.checked_add(rounded))?;
+        // A fee is subject to the same overdraft rules as a withdrawal.
+        if rounded < Decimal::ZERO && available < -config.overdraft_limit {
+            return Err(IgnoreReason::InsufficientFunds);
         }
+        checked(available.checked_add(self.held))?;
+        self.rounding_error += amount - rounded;
+        self.available = available;
+        self.insert_tx(Tx::new(tx, TxAmount::Adjustment(rounded)));
+        Ok(())
     }
 
-    fn dispute(&mut self, tx: u32) {
-        // Silently ignore non-existent txs
-        if let Some(tx) = self.get_tx(tx) {
-            tx.disputed = true;
+    fn dispute(&mut self, tx: u32, policy: DisputePolicy, overdraft_limit: Decimal) -> Applied {
+        // Ignore non-existent, already disputed, reversed or non-disputable txs
+        let (mut available, mut held, locked) = (self.available, self.held, self.locked);
+        let tx = self.get_tx(tx)?;
+        if tx.disputed {
+            return Err(IgnoreReason::Disputed);
+        }
+        if tx.reversed {
+            return Err(IgnoreReason::Reversed);
+        }
+        // A chargeback issued in error can be reopened after unlocking the client,
+        // reversing the chargeback to return to the disputed state.
+        let reopen = tx.charged_back;
+        if reopen && locked {
+            return Err(IgnoreReason::ChargedBack);
+        }
+        match (&tx.amount, policy) {
+            (TxAmount::Withdrawal(_), DisputePolicy::DepositsOnly)
+            | (TxAmount::Adjustment(_), _) => return Err(IgnoreReason::NotDisputable),
+            _ => {}
+        }
 
-            match tx.amount.clone() {
-                TxAmount::Deposit(amount) => {
-                    self.available -= amount;
-                    self.held += amount;
-                }
-                TxAmount::Withdrawal(amount) => {
-                    self.held += amount;
+        match tx.amount.clone() {
+            // The chargeback of a deposit already took the funds from available.
+            TxAmount::Deposit(amount) if reopen => {
+                held = checked(held.checked_add(amount))?;
+            }
+            TxAmount::Deposit(amount) => {
+                available = checked(available.checked_sub(amount))?;
+                held = checked(held.checked_add(amount))?;
+            }
+            // The chargeback of a withdrawal returned the funds to available.
+            TxAmount::Withdrawal(amount) if reopen => {
+                available = checked(available.checked_sub(amount))?;
+                if available < -overdraft_limit {
+                    return Err(IgnoreReason::InsufficientFunds);
                 }
+                held = checked(held.checked_add(amount))?;
+            }
+            TxAmount::Withdrawal(amount) => {
+                held = checked(held.checked_add(amount))?;
             }
+            TxAmount::Adjustment(_) => unreachable!("adjustments aren't disputable"),
         }
+        checked(available.checked_add(held))?;
+        tx.disputed = true;
+        tx.charged_back = false;
+        tx.held = tx.amount.value();
+        self.available = available;
+        self.held = held;
+        Ok(())
+    }
+
+    fn resolve(&mut self, tx: u32) -> Applied {
+        // Ignore non-existent txs or txs that aren't disputed
+        let (mut available, mut held) = (self.available, self.held);
+        let tx = self.get_tx(tx)?;
+        if !tx.disputed {
+            return Err(IgnoreReason::NotDisputed);
+        }
+        release(held, tx)?;
+
+        match tx.amount {
+            TxAmount::Deposit(_) => {
+                available += tx.held;
+                held -= tx.held;
+            }
+            TxAmount::Withdrawal(_) => {
+                held -= tx.held;
+            }
+            TxAmount::Adjustment(_) => return Err(IgnoreReason::NotDisputable),
+        }
+        tx.disputed = false;
+        tx.held = Decimal::ZERO;
+        self.available = available;
+        self.held = held;
+        Ok(())
     }
 
-    fn resolve(&mut self, tx: u32) {
-        // Silently ignore non-existent txs or txs that aren't disputed
-        if let Some(tx) = self.get_tx(tx) {
-            if !tx.disputed {
-                return;
+    fn chargeback(&mut self, tx: u32) -> Applied {
+        // Ignore non-existent txs or txs that aren't disputed
+        let (mut available, mut held) = (self.available, self.held);
+        let tx = self.get_tx(tx)?;
+        if !tx.disputed {
+            return Err(IgnoreReason::NotDisputed);
+        }
+        release(held, tx)?;
+
+        match tx.amount {
+            TxAmount::Deposit(_) => {
+                held -= tx.held;
             }
-            tx.disputed = false;
+            TxAmount::Withdrawal(_) => {
+                // Moves funds within the total claimed by the dispute, so it can't overflow.
+                available += tx.held;
+                held -= tx.held;
+            }
+            TxAmount::Adjustment(_) => return Err(IgnoreReason::NotDisputable),
+        }
+        tx.disputed = false;
+        tx.charged_back = true;
+        tx.held = Decimal::ZERO;
+        self.available = available;
+        self.held = held;
+        self.locked = true;
+        Ok(())
+    }
+
+    fn correction(&mut self, tx: u32, overdraft_limit: Decimal) -> Applied {
+        // Ignore non-existent, disputed, charged back or already reversed txs
+        let (available, held) = (self.available, self.held);
+        let tx = self.get_tx(tx)?;
+        if tx.disputed {
+            return Err(IgnoreReason::Disputed);
+        }
+        if tx.reversed {
+            return Err(IgnoreReason::Reversed);
+        }
+        // The chargeback already reversed the funds.
+        if tx.charged_back {
+            return Err(IgnoreReason::ChargedBack);
+        }
 
-            match tx.amount.clone() {
-                TxAmount::Deposit(amount) => {
-                    self.available += amount;
-                    self.held -= amount;
+        match tx.amount.clone() {
+            TxAmount::Deposit(amount) => {
+                // Like a withdrawal, a deposit can't be reversed without enough funds.
+                if checked(available.checked_sub(amount))? < -overdraft_limit {
+                    return Err(IgnoreReason::InsufficientFunds);
                 }
-                TxAmount::Withdrawal(amount) => {
-                    self.held -= amount;
+                tx.reversed = true;
+                self.available -= amount;
+                self.deposited -= amount;
+            }
+            TxAmount::Withdrawal(amount) => {
+                let available = checked(available.checked_add(amount))?;
+                checked(available.checked_add(held))?;
+                tx.reversed = true;
+                self.available = available;
+            }
+            TxAmount::Adjustment(amount) => {
+                let available = checked(available.checked_sub(amount))?;
+                if amount > Decimal::ZERO && available < -overdraft_limit {
+                    return Err(IgnoreReason::InsufficientFunds);
                 }
+                checked(available.checked_add(held))?;
+                tx.reversed = true;
+                self.available = available;
             }
         }
+        Ok(())
     }
 
-    fn chargeback(&mut self, tx: u32) {
-        // Silently ignore non-existent txs or txs that aren't disputed
-        if let Some(tx) = self.get_tx(tx) {
-            if !tx.disputed {
-                return;
+    /// The rows describing the client and its txs in a saved state.
+    ///
+    /// Txs are in the order they arrived, which restores the order of `transactions_of`
+    /// when loaded.
+    pub(crate) fn state_rows(&self) -> Vec<StateRow> {
+        let mut rows = vec![StateRow {
+            record: RecordKind::Client,
+            client: self.id,
+            available: Some(self.available),
+            held: Some(self.held),
+            locked: Some(self.locked),
+            tx: None,
+            amount: None,
+            disputed: None,
+            reversed: None,
+            currency: self.currency.clone(),
+            charged_back: None,
+        }];
+
+        let mut txs: Vec<&Tx> = self.txs.values().collect();
+        txs.sort_by_key(|tx| tx.arrival);
+        rows.extend(txs.into_iter().map(|tx| {
+            let (record, amount) = match tx.amount {
+                TxAmount::Deposit(amount) => (RecordKind::Deposit, amount),
+                TxAmount::Withdrawal(amount) => (RecordKind::Withdrawal, amount),
+                TxAmount::Adjustment(amount) => (RecordKind::Adjustment, amount),
+            };
+            StateRow {
+                record,
+                client: self.id,
+                available: None,
+                held: None,
+                locked: None,
+                tx: Some(tx.id),
+                amount: Some(amount),
+                disputed: Some(tx.disputed),
+                reversed: Some(tx.reversed),
+                currency: None,
+                charged_back: Some(tx.charged_back),
             }
-            tx.disputed = false;
+        }));
+        rows
+    }
 
-            match tx.amount.clone() {
-                TxAmount::Deposit(amount) => {
-                    self.held -= amount;
-                }
-                TxAmount::Withdrawal(amount) => {
-                    self.available += amount;
-                    self.held -= amount;
-                }
+    /// Restore a client from a client row in a saved state.
+    pub(crate) fn from_state(row: &StateRow) -> Result<Self> {
+        let mut client = Client::new(row.client);
+        client.control_only = false;
+        client.available = row
+            .available
+            .ok_or_else(|| anyhow!("Missing available for client {}", row.client))?;
+        client.held = row
+            .held
+            .ok_or_else(|| anyhow!("Missing held for client {}", row.client))?;
+        client.locked = row
+            .locked
+            .ok_or_else(|| anyhow!("Missing locked for client {}", row.client))?;
+        client.currency = row
+            .currency
+            .as_ref()
+            .map(|currency| currency.to_uppercase());
+        Ok(client)
+    }
+
+    /// Restore a tx from a tx row in a saved state.
+    pub(crate) fn restore_tx(&mut self, row: &StateRow) -> Result<()> {
+        let id = row
+            .tx
+            .ok_or_else(|| anyhow!("Missing tx for client {}", row.client))?;
+        let amount = row
+            .amount
+            .ok_or_else(|| anyhow!("Missing amount for tx {}", id))?;
+        let amount = match row.record {
+            RecordKind::Deposit => TxAmount::Deposit(amount),
+            RecordKind::Withdrawal => TxAmount::Withdrawal(amount),
+            RecordKind::Adjustment => TxAmount::Adjustment(amount),
+            RecordKind::Client => bail!("Expected a tx row for tx {}", id),
+        };
+        let mut tx = Tx::new(id, amount);
+        tx.disputed = row.disputed.unwrap_or(false);
+        tx.reversed = row.reversed.unwrap_or(false);
+        tx.charged_back = row.charged_back.unwrap_or(false);
+        if let TxAmount::Adjustment(_) = tx.amount {
+            if tx.disputed || tx.charged_back {
+                bail!("Adjustment tx {} can't be disputed or charged back", id);
             }
-            self.locked = true;
         }
+        if let (TxAmount::Deposit(amount), false) = (&tx.amount, tx.reversed) {
+            self.deposited += *amount;
+        }
+        if tx.disputed {
+            tx.held = tx.amount.value();
+        }
+        self.insert_tx(tx);
+        Ok(())
+    }
+
+    /// Unlock a client locked by a chargeback, without moving any funds.
+    pub fn unlock(&mut self) -> std::result::Result<(), IgnoreReason> {
+        if !self.locked {
+            return Err(IgnoreReason::NotLocked);
+        }
+        self.locked = false;
+        Ok(())
     }
 
-    fn insert_tx(&mut self, tx: Tx) {
+    fn insert_tx(&mut self, mut tx: Tx) {
+        tx.arrival = self.txs.len();
         self.txs.insert(tx.id, tx);
     }
 
-    fn get_tx(&mut self, tx: u32) -> Option<&mut Tx> {
-        self.txs.get_mut(&tx)
+    fn get_tx(&mut self, tx: u32) -> std::result::Result<&mut Tx, IgnoreReason> {
+        self.txs.get_mut(&tx).ok_or(IgnoreReason::UnknownTx)
     }
 }
 
@@ -188,23 +784,618 @@ impl Serialize for Client {
     }
 }
 
-#[derive(Debug, Serialize)]
-struct ClientOutput {
-    client: u16,
-    available: Decimal,
-    held: Decimal,
-    total: Decimal,
-    locked: bool,
+/// The output representation of a client.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct ClientOutput {
+    pub client: u32,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    #[serde(skip)]
+    pub locked_format: LockedFormat,
+    /// Only written in the extended output mode.
+    #[serde(skip)]
+    pub counts: Option<TxCounts>,
+    /// Only written in the extended output mode.
+    #[serde(skip)]
+    pub disputed: Option<Decimal>,
+    /// Only written if buckets are configured.
+    #[serde(skip)]
+    pub bucket: Option<String>,
+    /// Only written with the `fully_disputed` option.
+    #[serde(skip)]
+    pub fully_disputed: Option<bool>,
+    /// Only written with the `sums` option.
+    #[serde(skip)]
+    pub sums: Option<TxSums>,
+    /// Write amounts with this number of significant figures.
+    #[serde(skip)]
+    pub significant_figures: Option<u32>,
 }
 
-impl From<&Client> for ClientOutput {
-    fn from(client: &Client) -> Self {
+impl ClientOutput {
+    /// The names of the columns written with 'options'.
+    pub fn columns(options: &OutputOptions) -> Vec<&'static str> {
+        let mut columns = vec!["client", "available", "held", "total", "locked"];
+        if options.extended {
+            columns.extend_from_slice(&[
+                "deposits",
+                "withdrawals",
+                "disputes",
+                "resolves",
+                "chargebacks",
+                "corrections",
+                "unlocks",
+                "adjustments",
+                "disputed",
+            ]);
+        }
+        if !options.buckets.is_empty() {
+            columns.push("bucket");
+        }
+        if options.fully_disputed {
+            columns.push("fully_disputed");
+        }
+        if options.sums {
+            columns.extend_from_slice(&[
+                "total_deposited",
+                "total_withdrawn",
+                "total_disputed",
+                "total_charged_back",
+            ]);
+        }
+        columns
+    }
+
+    pub fn new(client: &Client, options: &OutputOptions) -> Self {
         Self {
             client: client.id,
             available: client.available,
             held: client.held,
             total: client.total(),
             locked: client.locked,
+            locked_format: options.locked,
+            counts: if options.extended {
+                Some(client.counts.clone())
+            } else {
+                None
+            },
+            disputed: if options.extended {
+                Some(client.disputed())
+            } else {
+                None
+            },
+            bucket: options.bucket(client.total()),
+            fully_disputed: if options.fully_disputed {
+                Some(client.fully_disputed())
+            } else {
+                None
+            },
+            sums: if options.sums {
+                Some(client.sums.clone())
+            } else {
+                None
+            },
+            significant_figures: options.significant_figures,
+        }
+    }
+}
+
+impl From<&Client> for ClientOutput {
+    fn from(client: &Client) -> Self {
+        Self::new(client, &OutputOptions::default())
+    }
+}
+
+// Serialize manually to support the different representations of `locked`
+// and the optional columns of the extended output mode.
+impl Serialize for ClientOutput {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = 5
+            + self.counts.as_ref().map_or(0, |_| 8)
+            + self.disputed.map_or(0, |_| 1)
+            + self.bucket.as_ref().map_or(0, |_| 1)
+            + self.fully_disputed.map_or(0, |_| 1)
+            + self.sums.as_ref().map_or(0, |_| 4);
+        let mut row = serializer.serialize_struct("ClientOutput", len)?;
+        row.serialize_field("client", &self.client)?;
+        match self.significant_figures {
+            None => {
+                row.serialize_field("available", &self.available)?;
+                row.serialize_field("held", &self.held)?;
+                row.serialize_field("total", &self.total)?;
+            }
+            Some(figures) => {
+                row.serialize_field("available", &significant(self.available, figures))?;
+                row.serialize_field("held", &significant(self.held, figures))?;
+                row.serialize_field("total", &significant(self.total, figures))?;
+            }
+        }
+        match self.locked_format {
+            LockedFormat::TrueFalse => row.serialize_field("locked", &self.locked)?,
+            LockedFormat::OneZero => row.serialize_field("locked", &(self.locked as u8))?,
+            LockedFormat::YesNo => {
+                row.serialize_field("locked", if self.locked { "yes" } else { "no" })?
+            }
+        }
+        if let Some(counts) = &self.counts {
+            row.serialize_field("deposits", &counts.deposits)?;
+            row.serialize_field("withdrawals", &counts.withdrawals)?;
+            row.serialize_field("disputes", &counts.disputes)?;
+            row.serialize_field("resolves", &counts.resolves)?;
+            row.serialize_field("chargebacks", &counts.chargebacks)?;
+            row.serialize_field("corrections", &counts.corrections)?;
+            row.serialize_field("unlocks", &counts.unlocks)?;
+            row.serialize_field("adjustments", &counts.adjustments)?;
+        }
+        if let Some(disputed) = &self.disputed {
+            row.serialize_field("disputed", disputed)?;
+        }
+        if let Some(bucket) = &self.bucket {
+            row.serialize_field("bucket", bucket)?;
+        }
+        if let Some(fully_disputed) = &self.fully_disputed {
+            row.serialize_field("fully_disputed", fully_disputed)?;
         }
+        if let Some(sums) = &self.sums {
+            row.serialize_field("total_deposited", &sums.deposited)?;
+            row.serialize_field("total_withdrawn", &sums.withdrawn)?;
+            row.serialize_field("total_disputed", &sums.disputed)?;
+            row.serialize_field("total_charged_back", &sums.charged_back)?;
+        }
+        row.end()
+    }
+}
+
+/// The state of a client for FFI consumers, with amounts in minor units.
+///
+/// An amount is `value * 10^scale`, like `1234` for `12.34` with a scale of 2.
+/// Amounts beyond the range of `i128` saturate.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClientRecord {
+    pub client: u32,
+    pub available: i128,
+    pub held: i128,
+    pub total: i128,
+    /// The number of decimal places of the amounts, the configured precision.
+    pub scale: u32,
+    pub locked: bool,
+}
+
+impl ClientRecord {
+    pub fn new(client: &Client, scale: u32) -> Self {
+        Self {
+            client: client.id,
+            available: minor_units(client.available, scale),
+            held: minor_units(client.held, scale),
+            total: minor_units(client.total(), scale),
+            scale,
+            locked: client.locked,
+        }
+    }
+}
+
+fn minor_units(amount: Decimal, scale: u32) -> i128 {
+    let mut amount = amount;
+    amount.rescale(scale);
+    // Large amounts can't be rescaled all the way.
+    let missing = scale.saturating_sub(amount.scale());
+    10i128
+        .checked_pow(missing)
+        .and_then(|factor| amount.mantissa().checked_mul(factor))
+        .unwrap_or(if amount.is_sign_negative() {
+            i128::MIN
+        } else {
+            i128::MAX
+        })
+}
+
+/// Format 'amount' with 'figures' significant figures.
+///
+/// Amounts rounding to an integer of exactly 'figures' digits are written as is, like `123`,
+/// other amounts in scientific notation, like `1.235e2`.
+fn significant(amount: Decimal, figures: u32) -> String {
+    let figures = figures.max(1);
+    let digits_and_exponent = |amount: Decimal| {
+        let amount = amount.abs().normalize();
+        let digits = amount.mantissa().to_string();
+        let exponent = digits.len() as i64 - 1 - amount.scale() as i64;
+        (digits, exponent)
+    };
+
+    if amount.is_zero() {
+        return "0".to_string();
+    }
+    let (_, exponent) = digits_and_exponent(amount);
+    let places = figures as i64 - 1 - exponent;
+    let (rounded, shift) = if places >= 0 {
+        let rounded =
+            amount.round_dp_with_strategy(places as u32, RoundingStrategy::MidpointAwayFromZero);
+        (rounded, 0)
+    } else {
+        // Keep the rounded amount scaled down, as scaling it back up may overflow.
+        let factor = Decimal::from_i128_with_scale(10i128.pow((-places) as u32), 0);
+        let rounded =
+            (amount / factor).round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero);
+        (rounded, -places)
+    };
+    // Rounding may carry into another digit, like 9.99 to 10.0.
+    let (mut digits, exponent) = digits_and_exponent(rounded);
+    let exponent = exponent + shift;
+    if exponent == figures as i64 - 1 {
+        return rounded.round_dp(0).to_string();
+    }
+    digits.truncate(figures as usize);
+    while digits.len() < figures as usize {
+        digits.push('0');
+    }
+    let sign = if rounded.is_sign_negative() { "-" } else { "" };
+    let (first, rest) = digits.split_at(1);
+    if rest.is_empty() {
+        format!("{}{}e{}", sign, first, exponent)
+    } else {
+        format!("{}{}.{}e{}", sign, first, rest, exponent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn execute(client: &mut Client, tx: u32, variant: TransactionVariant) -> Option<IgnoreReason> {
+        execute_with(client, tx, variant, &Config::default())
+    }
+
+    fn execute_with(
+        client: &mut Client,
+        tx: u32,
+        variant: TransactionVariant,
+        config: &Config,
+    ) -> Option<IgnoreReason> {
+        let t = Transaction {
+            client: client.id,
+            tx,
+            variant,
+            currency: None,
+        };
+        client.execute(t, config).unwrap()
+    }
+
+    fn withdrawal(amount: &str) -> TransactionVariant {
+        TransactionVariant::Withdrawal {
+            amount: amount.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn ignore_reasons() {
+        let mut client = Client::new(1);
+        let deposit = TransactionVariant::Deposit {
+            amount: Decimal::ONE,
+        };
+        let withdrawal = TransactionVariant::Withdrawal {
+            amount: Decimal::new(2, 0),
+        };
+        assert_eq!(execute(&mut client, 1, deposit), None);
+        assert_eq!(
+            execute(&mut client, 2, withdrawal),
+            Some(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            execute(&mut client, 2, TransactionVariant::Dispute),
+            Some(IgnoreReason::UnknownTx)
+        );
+        assert_eq!(
+            execute(&mut client, 1, TransactionVariant::Resolve),
+            Some(IgnoreReason::NotDisputed)
+        );
+        assert_eq!(
+            execute(&mut client, 1, TransactionVariant::Chargeback),
+            Some(IgnoreReason::NotDisputed)
+        );
+        assert_eq!(client.available(), Decimal::ONE);
+    }
+
+    #[test]
+    fn overdraft_limit() {
+        let config = Config {
+            overdraft_limit: Decimal::ONE,
+            ..Config::default()
+        };
+        let mut client = Client::new(1);
+        let deposit = TransactionVariant::Deposit {
+            amount: Decimal::ONE,
+        };
+        assert_eq!(execute_with(&mut client, 1, deposit, &config), None);
+        assert_eq!(
+            execute_with(&mut client, 2, withdrawal("1.5"), &config),
+            None
+        );
+        assert_eq!(client.available(), "-0.5".parse().unwrap());
+        assert_eq!(
+            execute_with(&mut client, 3, withdrawal("0.6"), &config),
+            Some(IgnoreReason::InsufficientFunds)
+        );
+        assert_eq!(
+            execute_with(&mut client, 4, withdrawal("0.5"), &config),
+            None
+        );
+        assert_eq!(client.available(), "-1.0".parse().unwrap());
+    }
+
+    #[test]
+    fn adjustments_are_not_disputable() {
+        let mut client = Client::new(1);
+        let deposit = TransactionVariant::Deposit {
+            amount: Decimal::ONE,
+        };
+        let fee = TransactionVariant::Adjustment {
+            amount: "-0.25".parse().unwrap(),
+        };
+        assert_eq!(execute(&mut client, 1, deposit), None);
+        assert_eq!(execute(&mut client, 2, fee), None);
+        assert_eq!(
+            execute(&mut client, 2, TransactionVariant::Dispute),
+            Some(IgnoreReason::NotDisputable)
+        );
+        assert_eq!(client.available(), "0.75".parse().unwrap());
+        assert_eq!(client.held(), Decimal::ZERO);
+
+        let fee = TransactionVariant::Adjustment {
+            amount: "-1".parse().unwrap(),
+        };
+        assert_eq!(
+            execute(&mut client, 3, fee),
+            Some(IgnoreReason::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn disputed_lifecycle() {
+        let mut client = Client::new(1);
+        let deposit = |amount| TransactionVariant::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        execute(&mut client, 1, deposit(5));
+        execute(&mut client, 2, deposit(3));
+        execute(&mut client, 3, withdrawal("2"));
+        assert_eq!(client.disputed(), Decimal::ZERO);
+
+        execute(&mut client, 1, TransactionVariant::Dispute);
+        assert_eq!(client.disputed(), Decimal::new(5, 0));
+        execute(&mut client, 3, TransactionVariant::Dispute);
+        assert_eq!(client.disputed(), Decimal::new(7, 0));
+        assert_eq!(client.disputed(), client.held());
+
+        execute(&mut client, 1, TransactionVariant::Resolve);
+        assert_eq!(client.disputed(), Decimal::new(2, 0));
+        assert_eq!(client.disputed(), client.held());
+
+        // Neither a repeated resolve nor a missing tx changes the disputed amount.
+        execute(&mut client, 1, TransactionVariant::Resolve);
+        execute(&mut client, 9, TransactionVariant::Chargeback);
+        assert_eq!(client.disputed(), Decimal::new(2, 0));
+
+        execute(&mut client, 2, TransactionVariant::Dispute);
+        execute(&mut client, 3, TransactionVariant::Chargeback);
+        assert_eq!(client.disputed(), Decimal::new(3, 0));
+        execute(&mut client, 2, TransactionVariant::Chargeback);
+        assert_eq!(client.disputed(), Decimal::ZERO);
+        assert_eq!(client.disputed(), client.held());
+    }
+
+    #[test]
+    fn overflow_is_ignored() {
+        let mut client = Client::new(1);
+        let max = TransactionVariant::Deposit {
+            amount: Decimal::MAX,
+        };
+        let one = TransactionVariant::Deposit {
+            amount: Decimal::ONE,
+        };
+        assert_eq!(execute(&mut client, 1, max), None);
+        assert_eq!(execute(&mut client, 2, one), Some(IgnoreReason::Overflow));
+        assert_eq!(client.available(), Decimal::MAX);
+        assert_eq!(
+            execute(&mut client, 2, TransactionVariant::Dispute),
+            Some(IgnoreReason::UnknownTx)
+        );
+
+        let config = Config {
+            overdraft_limit: Decimal::MAX,
+            ..Config::default()
+        };
+        let mut client = Client::new(2);
+        let max = TransactionVariant::Withdrawal {
+            amount: Decimal::MAX,
+        };
+        assert_eq!(execute_with(&mut client, 3, max, &config), None);
+        assert_eq!(
+            execute_with(&mut client, 4, withdrawal("1"), &config),
+            Some(IgnoreReason::Overflow)
+        );
+        assert_eq!(client.available(), -Decimal::MAX);
+    }
+
+    #[test]
+    fn no_overdraft_by_default() {
+        let mut client = Client::new(1);
+        let deposit = TransactionVariant::Deposit {
+            amount: Decimal::ONE,
+        };
+        assert_eq!(execute(&mut client, 1, deposit), None);
+        assert_eq!(
+            execute(&mut client, 2, withdrawal("1.0001")),
+            Some(IgnoreReason::InsufficientFunds)
+        );
+    }
+
+    #[test]
+    fn rounding_account() {
+        let config = Config {
+            rounding_account: true,
+            ..Config::default()
+        };
+        let mut client = Client::new(1);
+        let deposit = TransactionVariant::Deposit {
+            amount: "1.23456".parse().unwrap(),
+        };
+        assert_eq!(execute_with(&mut client, 1, deposit, &config), None);
+        // Rounding up leaves a negative remainder.
+        assert_eq!(client.available(), "1.2346".parse().unwrap());
+        assert_eq!(client.rounding_account(), "-0.00004".parse().unwrap());
+
+        let deposit = TransactionVariant::Deposit {
+            amount: "1.00001".parse().unwrap(),
+        };
+        assert_eq!(execute_with(&mut client, 2, deposit, &config), None);
+        assert_eq!(client.rounding_account(), "-0.00003".parse().unwrap());
+
+        let deposit = TransactionVariant::Deposit {
+            amount: "1.00004".parse().unwrap(),
+        };
+        assert_eq!(execute_with(&mut client, 3, deposit, &config), None);
+        assert_eq!(client.rounding_account(), "0.00001".parse().unwrap());
+        // Together with the rounding account no funds are lost or created.
+        assert_eq!(
+            client.total() + client.rounding_account(),
+            "3.23461".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn accumulated_rounding_error() {
+        let mut client = Client::new(1);
+        for (tx, amount) in ["1.00001", "2.00002", "0.00003", "1.5"].iter().enumerate() {
+            let deposit = TransactionVariant::Deposit {
+                amount: amount.parse().unwrap(),
+            };
+            assert_eq!(execute(&mut client, tx as u32, deposit), None);
+        }
+        assert_eq!(client.available(), "4.5".parse().unwrap());
+        assert_eq!(
+            client.accumulated_rounding_error(),
+            "0.00006".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn significant_figures() {
+        let amount: Decimal = "123.456".parse().unwrap();
+        assert_eq!(significant(amount, 4), "1.235e2");
+        assert_eq!(significant(amount, 3), "123");
+        assert_eq!(significant(amount, 2), "1.2e2");
+        assert_eq!(significant(amount, 1), "1e2");
+        assert_eq!(significant(-amount, 4), "-1.235e2");
+        assert_eq!(significant("0.00123".parse().unwrap(), 2), "1.2e-3");
+        assert_eq!(significant("9.99".parse().unwrap(), 2), "10");
+        assert_eq!(significant("99.9".parse().unwrap(), 2), "1.0e2");
+        assert_eq!(significant("95".parse().unwrap(), 2), "95");
+        assert_eq!(significant(Decimal::ZERO, 3), "0");
+        assert_eq!(significant(Decimal::MAX, 1), "8e28");
+        assert_eq!(significant(Decimal::MAX, 0), "8e28");
+        assert_eq!(significant(Decimal::MAX, 2), "7.9e28");
+        assert_eq!(significant(Decimal::MIN, 1), "-8e28");
+    }
+
+    #[test]
+    fn transactions_of() {
+        let mut client = Client::new(1);
+        let deposit = |amount| TransactionVariant::Deposit {
+            amount: Decimal::new(amount, 0),
+        };
+        execute(&mut client, 9, deposit(5));
+        execute(&mut client, 3, withdrawal("1"));
+        execute(&mut client, 7, deposit(2));
+        execute(&mut client, 7, TransactionVariant::Dispute);
+        execute(&mut client, 8, withdrawal("100"));
+        execute(&mut client, 1, deposit(3));
+
+        let deposits: Vec<(u32, Decimal, bool)> = client
+            .transactions_of(TxKind::Deposit)
+            .into_iter()
+            .map(|tx| (tx.id(), tx.amount(), tx.disputed()))
+            .collect();
+        assert_eq!(
+            deposits,
+            vec![
+                (9, Decimal::new(5, 0), false),
+                (7, Decimal::new(2, 0), true),
+                (1, Decimal::new(3, 0), false),
+            ]
+        );
+        let withdrawals = client.transactions_of(TxKind::Withdrawal);
+        assert_eq!(withdrawals.len(), 1);
+        assert_eq!(withdrawals[0].id(), 3);
+        assert!(client.transactions_of(TxKind::Adjustment).is_empty());
+    }
+
+    #[test]
+    fn concurrent_disputes_in_any_order() {
+        let deposit = |amount| TransactionVariant::Deposit {
+            amount: Decimal::new(amount, 1),
+        };
+        let settle = |order: &[(u32, TransactionVariant)]| {
+            let mut client = Client::new(1);
+            execute(&mut client, 1, deposit(55));
+            execute(&mut client, 2, deposit(25));
+            execute(&mut client, 3, withdrawal("1.5"));
+            execute(&mut client, 4, deposit(100));
+            execute(&mut client, 1, TransactionVariant::Dispute);
+            execute(&mut client, 3, TransactionVariant::Dispute);
+            execute(&mut client, 2, TransactionVariant::Dispute);
+            // Disputing a tx again doesn't hold its amount twice.
+            assert_eq!(
+                execute(&mut client, 2, TransactionVariant::Dispute),
+                Some(IgnoreReason::Disputed)
+            );
+            assert_eq!(client.disputed(), client.held());
+            for (tx, variant) in order {
+                assert_eq!(execute(&mut client, *tx, variant.clone()), None);
+                assert_eq!(client.disputed(), client.held());
+            }
+            (client.available(), client.held(), client.total())
+        };
+
+        let resolved = settle(&[
+            (1, TransactionVariant::Resolve),
+            (3, TransactionVariant::Resolve),
+            (2, TransactionVariant::Resolve),
+        ]);
+        assert_eq!(
+            resolved,
+            settle(&[
+                (2, TransactionVariant::Resolve),
+                (1, TransactionVariant::Resolve),
+                (3, TransactionVariant::Resolve),
+            ])
+        );
+        assert_eq!(
+            resolved,
+            (Decimal::new(165, 1), Decimal::ZERO, Decimal::new(165, 1))
+        );
+
+        let mixed = settle(&[
+            (3, TransactionVariant::Resolve),
+            (1, TransactionVariant::Chargeback),
+            (2, TransactionVariant::Resolve),
+        ]);
+        assert_eq!(
+            mixed,
+            settle(&[
+                (2, TransactionVariant::Resolve),
+                (1, TransactionVariant::Chargeback),
+                (3, TransactionVariant::Resolve),
+            ])
+        );
+        assert_eq!(
+            mixed,
+            (Decimal::new(11, 0), Decimal::ZERO, Decimal::new(11, 0))
+        );
     }
 }
@@ -0,0 +1,263 @@
+use csv::StringRecord;
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+/// Configuration of how the atm processes transactions.
+///
+/// The default configuration processes transactions as described in the README.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub aliases: Aliases,
+    /// The number of decimal places amounts are rounded to.
+    pub precision: u32,
+    pub dispute_policy: DisputePolicy,
+    /// Fail if a client's held funds ever exceed the sum of their deposits.
+    pub check_held_invariant: bool,
+    /// Log ignored transactions to stderr.
+    pub verbose: bool,
+    /// Treat malformed rows, like a dispute carrying an amount, as errors.
+    pub strict: bool,
+    /// Fail on the first transaction that would be ignored.
+    pub fail_fast: bool,
+    /// The maximum number of digits in the integer part of an amount, longer amounts
+    /// are treated as malformed.
+    pub max_integer_digits: Option<usize>,
+    /// Treat amounts without a decimal point, like `100` instead of `100.00`, as malformed.
+    pub require_decimal_point: bool,
+    /// How far below zero the available funds of a client may go.
+    pub overdraft_limit: Decimal,
+    /// Ignore deposits and withdrawals for locked clients.
+    pub reject_locked: bool,
+    /// Route the signed remainders of rounded deposits to a per client rounding account.
+    pub rounding_account: bool,
+    /// Read the first row of the input as a header. Without a header the columns are
+    /// `type,client,tx,amount`, and a first row that isn't a transaction is skipped as a
+    /// stray header.
+    pub has_headers: bool,
+    /// Accept a zero-byte input without a header as an input without transactions,
+    /// instead of failing.
+    pub allow_empty_input: bool,
+    /// The field delimiter of the input.
+    pub delimiter: u8,
+    /// The quote character of the input.
+    pub quote: u8,
+    /// The number of rows buffered between reading and processing in `Atm::process_buffered`.
+    pub channel_capacity: usize,
+    /// The number of threads clients are processed on by `Atm::process_reader`.
+    pub workers: usize,
+    /// Report txs left disputed at the end of the input.
+    pub report_unresolved_disputes: bool,
+    /// What happens to txs left disputed at the end of the input.
+    pub eof_disputes: EofDisputePolicy,
+    /// Record why transactions were ignored, to be queried with `Atm::why_ignored`.
+    pub record_ignored: bool,
+    /// Log every applied and ignored transaction, to be queried with `Atm::events`.
+    pub event_log: bool,
+    /// Skip a trailing footer row with this value in the type column, like `TOTAL`.
+    pub footer: Option<String>,
+    /// Convert non-ASCII decimal digits, like Arabic-Indic digits, to ASCII before parsing.
+    #[cfg(feature = "unicode-digits")]
+    pub normalize_digits: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            aliases: Aliases::default(),
+            precision: 4,
+            dispute_policy: DisputePolicy::default(),
+            check_held_invariant: false,
+            verbose: false,
+            strict: false,
+            fail_fast: false,
+            max_integer_digits: None,
+            require_decimal_point: false,
+            overdraft_limit: Decimal::ZERO,
+            reject_locked: false,
+            rounding_account: false,
+            has_headers: true,
+            allow_empty_input: false,
+            delimiter: b',',
+            quote: b'"',
+            channel_capacity: 1024,
+            workers: 1,
+            report_unresolved_disputes: false,
+            eof_disputes: EofDisputePolicy::default(),
+            record_ignored: false,
+            event_log: false,
+            footer: None,
+            #[cfg(feature = "unicode-digits")]
+            normalize_digits: false,
+        }
+    }
+}
+
+/// Which transactions can be disputed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputePolicy {
+    /// Both deposits and withdrawals.
+    #[default]
+    All,
+    /// Only deposits.
+    DepositsOnly,
+}
+
+/// What happens to txs left disputed at the end of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofDisputePolicy {
+    /// Keep the funds held.
+    #[default]
+    Keep,
+    /// Resolve the disputes, releasing the funds.
+    Resolve,
+    /// Charge back the disputes, locking the clients.
+    Chargeback,
+}
+
+/// Options for how the state of clients is written.
+#[derive(Debug, Clone)]
+pub struct OutputOptions {
+    pub locked: LockedFormat,
+    /// Add columns with the number of applied transactions of each type.
+    pub extended: bool,
+    /// Skip clients with a nonzero balance but without any txs, as loaded from a checkpoint.
+    pub skip_balances_without_txs: bool,
+    /// Write the header in uppercase, such as `CLIENT,AVAILABLE,HELD,TOTAL,LOCKED`.
+    pub uppercase_header: bool,
+    /// The field delimiter of the output.
+    pub delimiter: u8,
+    /// The quote character of the output.
+    pub quote: u8,
+    /// Ascending upper bounds of buckets to assign clients to by their total, written
+    /// to a `bucket` column if not empty.
+    pub buckets: Vec<Decimal>,
+    /// Write amounts with this number of significant figures instead of as is.
+    pub significant_figures: Option<u32>,
+    /// Append a `# clients=N sha256=...` line with the number of clients and the SHA-256
+    /// of the preceding output, to detect truncation or corruption.
+    pub checksum: bool,
+    /// Add a `fully_disputed` column flagging clients with no available funds but held
+    /// funds, so all their funds are disputed.
+    pub fully_disputed: bool,
+    /// Add columns with the sums of the amounts deposited, withdrawn, disputed and charged
+    /// back by each client.
+    pub sums: bool,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            locked: LockedFormat::default(),
+            extended: false,
+            skip_balances_without_txs: false,
+            uppercase_header: false,
+            delimiter: b',',
+            quote: b'"',
+            buckets: Vec::new(),
+            significant_figures: None,
+            checksum: false,
+            fully_disputed: false,
+            sums: false,
+        }
+    }
+}
+
+impl OutputOptions {
+    /// The label of the bucket 'total' falls in, like `<=0`, `0-100` or `>100` for
+    /// the bounds 0 and 100.
+    pub fn bucket(&self, total: Decimal) -> Option<String> {
+        let last = self.buckets.last()?;
+        let label = match self.buckets.iter().position(|bound| total <= *bound) {
+            Some(0) => format!("<={}", self.buckets[0]),
+            Some(i) => format!("{}-{}", self.buckets[i - 1], self.buckets[i]),
+            None => format!(">{}", last),
+        };
+        Some(label)
+    }
+}
+
+/// The representation of the `locked` column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockedFormat {
+    /// `true`/`false`
+    #[default]
+    TrueFalse,
+    /// `1`/`0`
+    OneZero,
+    /// `yes`/`no`
+    YesNo,
+}
+
+/// Maps header and transaction type variants onto their canonical names.
+///
+/// Names are compared case-insensitively, so `Deposit` and `DEPOSIT` both map to `deposit`
+/// without an explicit alias.
+#[derive(Debug, Clone)]
+pub struct Aliases {
+    headers: HashMap<String, String>,
+    types: HashMap<String, String>,
+}
+
+impl Aliases {
+    /// An empty mapping, only normalizing the casing.
+    pub fn new() -> Self {
+        Self {
+            headers: HashMap::new(),
+            types: HashMap::new(),
+        }
+    }
+
+    /// Treat the header column `alias` as `canonical`.
+    pub fn header(mut self, alias: &str, canonical: &str) -> Self {
+        self.headers
+            .insert(alias.to_lowercase(), canonical.to_lowercase());
+        self
+    }
+
+    /// Treat the transaction type `alias` as `canonical`.
+    pub fn type_name(mut self, alias: &str, canonical: &str) -> Self {
+        self.types
+            .insert(alias.to_lowercase(), canonical.to_lowercase());
+        self
+    }
+
+    /// Normalize a header record to the canonical column names.
+    pub fn normalize_headers(&self, headers: &StringRecord) -> StringRecord {
+        headers
+            .iter()
+            .map(|header| normalize(&self.headers, header))
+            .collect()
+    }
+
+    /// Normalize the transaction type found in the column `type_column`.
+    pub fn normalize_record(&self, record: &StringRecord, type_column: usize) -> StringRecord {
+        let mut normalized: StringRecord = record
+            .iter()
+            .enumerate()
+            .map(|(i, field)| {
+                if i == type_column {
+                    normalize(&self.types, field)
+                } else {
+                    field.to_string()
+                }
+            })
+            .collect();
+        normalized.set_position(record.position().cloned());
+        normalized
+    }
+}
+
+impl Default for Aliases {
+    /// Accepts `action` as the type column.
+    fn default() -> Self {
+        Self::new().header("action", "type")
+    }
+}
+
+fn normalize(aliases: &HashMap<String, String>, name: &str) -> String {
+    let name = name.to_lowercase();
+    match aliases.get(&name) {
+        Some(canonical) => canonical.clone(),
+        None => name,
+    }
+}
@@ -0,0 +1,46 @@
+use rust_decimal::Decimal;
+use std::fmt;
+
+/// A client breaking one of the invariants of the atm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub client: u32,
+    pub kind: ViolationKind,
+}
+
+/// The invariant that's broken.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ViolationKind {
+    /// Available funds below the overdraft limit.
+    NegativeAvailable(Decimal),
+    /// Held funds below zero.
+    NegativeHeld(Decimal),
+    /// The total can't be represented as available plus held.
+    TotalMismatch,
+    /// Held funds differ from the sum of the disputed txs.
+    HeldMismatch { held: Decimal, disputed: Decimal },
+    /// Available funds with more decimal places than the configured precision.
+    AvailablePrecision(Decimal),
+    /// Held funds with more decimal places than the configured precision.
+    HeldPrecision(Decimal),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {}: ", self.client)?;
+        match &self.kind {
+            ViolationKind::NegativeAvailable(available) => {
+                write!(f, "available {} below the overdraft limit", available)
+            }
+            ViolationKind::NegativeHeld(held) => write!(f, "held {} below zero", held),
+            ViolationKind::TotalMismatch => f.write_str("total isn't available plus held"),
+            ViolationKind::HeldMismatch { held, disputed } => {
+                write!(f, "held {} differs from disputed {}", held, disputed)
+            }
+            ViolationKind::AvailablePrecision(available) => {
+                write!(f, "available {} exceeds the precision", available)
+            }
+            ViolationKind::HeldPrecision(held) => write!(f, "held {} exceeds the precision", held),
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+
+use crate::atm::Transaction;
+use crate::client::{Client, IgnoreReason};
+
+/// An entry in the event log of an atm.
+///
+/// Serialized with an `event` field of `applied` or `skipped`, like
+/// `{"event":"applied","transaction":{..},"before":{..},"after":{..},"locked":false}`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event")]
+#[serde(rename_all = "lowercase")]
+pub enum LedgerEvent {
+    /// A transaction changed the state of a client.
+    Applied {
+        transaction: Transaction,
+        before: Balances,
+        after: Balances,
+        /// If the transaction locked the client.
+        locked: bool,
+    },
+    /// A transaction was ignored.
+    Skipped {
+        transaction: Transaction,
+        #[serde(serialize_with = "serialize_reason")]
+        reason: IgnoreReason,
+    },
+}
+
+/// The funds of a client at a point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Balances {
+    pub available: Decimal,
+    pub held: Decimal,
+}
+
+impl From<&Client> for Balances {
+    fn from(client: &Client) -> Self {
+        Self {
+            available: client.available(),
+            held: client.held(),
+        }
+    }
+}
+
+fn serialize_reason<S>(reason: &IgnoreReason, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_str(reason)
+}
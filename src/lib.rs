@@ -0,0 +1,14 @@
+pub mod atm;
+pub mod builder;
+pub mod client;
+pub mod config;
+pub mod invariant;
+pub mod ledger;
+mod reader;
+pub mod reconcile;
+pub mod report;
+mod sha256;
+pub mod source;
+pub mod state;
+pub mod stream;
+pub mod validate;
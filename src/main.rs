@@ -3,10 +3,9 @@ use std::env;
 use std::ffi::OsString;
 use std::path::PathBuf;
 
-mod atm;
-mod client;
-
-use atm::Atm;
+use rt_test::atm::Atm;
+use rt_test::builder::AtmBuilder;
+use rt_test::config::OutputOptions;
 
 fn main() {
     if let Err(err) = run() {
@@ -15,19 +14,75 @@ fn main() {
 }
 
 fn run() -> Result<()> {
-    let path: PathBuf = input_path()?.into();
-    let atm = Atm::from_path(&path)?;
-    atm.print_csv()?;
-    Ok(())
+    let args = parse_args(env::args_os().skip(1))?;
+    let atm = process(&args)?;
+    write_output(&atm, &args)
 }
 
-fn input_path() -> anyhow::Result<OsString> {
-    match env::args_os().nth(1) {
-        None => Err(anyhow!("Please provide an input file")),
-        Some(path) => Ok(path),
+/// Write the state to the output file if given, otherwise to stdout.
+fn write_output(atm: &Atm, args: &Args) -> Result<()> {
+    match &args.output {
+        Some(path) => atm.write_csv_to_path(path, &OutputOptions::default()),
+        None => atm.print_csv(),
     }
 }
 
+/// Process all input files in order into a single atm.
+fn process(args: &Args) -> Result<Atm> {
+    let mut atm = AtmBuilder::new()
+        .verbose(args.verbose)
+        .strict(args.strict)
+        .fail_fast(args.fail_fast)
+        .build();
+    for input in &args.inputs {
+        atm.process_path(input)?;
+    }
+    atm.finish()?;
+    Ok(atm)
+}
+
+struct Args {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    verbose: bool,
+    strict: bool,
+    fail_fast: bool,
+}
+
+fn parse_args(mut args: impl Iterator<Item = OsString>) -> Result<Args> {
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut verbose = false;
+    let mut strict = false;
+    let mut fail_fast = false;
+    while let Some(arg) = args.next() {
+        if arg == "-o" || arg == "--output" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow!("Please provide a path for {:?}", arg))?;
+            output = Some(path.into());
+        } else if arg == "-v" || arg == "--verbose" {
+            verbose = true;
+        } else if arg == "--strict" {
+            strict = true;
+        } else if arg == "--fail-fast" {
+            fail_fast = true;
+        } else {
+            inputs.push(arg.into());
+        }
+    }
+    if inputs.is_empty() {
+        return Err(anyhow!("Please provide an input file"));
+    }
+    Ok(Args {
+        inputs,
+        output,
+        verbose,
+        strict,
+        fail_fast,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -61,13 +116,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn multiple_input_files() {
+        let args = parse_args(
+            ["test_files/split/part1.csv", "test_files/split/part2.csv"]
+                .iter()
+                .map(OsString::from),
+        )
+        .unwrap();
+        let got = process(&args).unwrap().to_csv_string().unwrap();
+        let expected = Atm::from_path(Path::new("test_files/base-input.in"))
+            .unwrap()
+            .to_csv_string()
+            .unwrap();
+        assert_eq!(sort_lines(got), sort_lines(expected));
+    }
+
+    #[test]
+    fn output_file() {
+        let output = env::temp_dir().join(format!("rt-test-output-{}.csv", std::process::id()));
+        let args = parse_args(
+            vec![
+                OsString::from("test_files/base-input.in"),
+                OsString::from("-o"),
+                output.clone().into(),
+            ]
+            .into_iter(),
+        )
+        .unwrap();
+        let atm = process(&args).unwrap();
+        write_output(&atm, &args).unwrap();
+        let written = fs::read_to_string(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        assert_eq!(written, atm.to_csv_string().unwrap());
+
+        let missing = parse_args(
+            ["test_files/base-input.in", "--output"]
+                .iter()
+                .map(OsString::from),
+        );
+        assert!(missing.is_err());
+    }
+
     fn assert_output(in_path: &Path, out_path: &Path) {
         // Note that this holds the contents of both the files in memory (and does a string split
         // and sorts them) so it's not efficient, but it's fine for smaller files.
-        let atm = Atm::from_path(in_path).expect(&format!("failed to process {:?}", in_path));
+        let atm = Atm::from_path(in_path)
+            .unwrap_or_else(|err| panic!("failed to process {:?}: {:#}", in_path, err));
         let got = sort_lines(atm.to_csv_string().expect("failed to write csv string"));
         let expected = sort_lines(
-            fs::read_to_string(out_path).expect(&format!("failed to read {:?}", out_path)),
+            fs::read_to_string(out_path)
+                .unwrap_or_else(|_| panic!("failed to read {:?}", out_path)),
         );
         assert_eq!(got, expected, "failed to match {:?}", in_path);
     }
@@ -0,0 +1,366 @@
+use anyhow::{anyhow, bail, Context, Result};
+use csv::{Reader, ReaderBuilder, StringRecord, Trim};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+
+use crate::atm::{Transaction, TransactionVariant};
+use crate::config::{Aliases, Config};
+
+/// A row read from the input.
+pub(crate) enum Row {
+    Transaction(Transaction),
+    /// A malformed row skipped outside of strict mode, with the reason why.
+    Skipped(String),
+}
+
+/// Reads transactions in a csv format.
+///
+/// Headers and transaction types are normalized using the configured aliases
+/// before they're deserialized.
+pub(crate) struct TransactionReader<R> {
+    reader: Reader<R>,
+    aliases: Aliases,
+    strict: bool,
+    max_integer_digits: Option<usize>,
+    require_decimal_point: bool,
+    footer: Option<String>,
+    #[cfg(feature = "unicode-digits")]
+    normalize_digits: bool,
+    headers: StringRecord,
+    type_column: usize,
+    amount_column: Option<usize>,
+    seq_column: Option<usize>,
+    /// The sequence number of the latest row of each client.
+    sequences: HashMap<u32, u64>,
+    /// If the next row may be a stray header, in an input without headers.
+    header_pending: bool,
+    record: StringRecord,
+}
+
+impl<R: io::Read> TransactionReader<R> {
+    pub(crate) fn new(reader: R, config: &Config) -> Result<Self> {
+        // Column counts are validated when reading the records, to be able to report them
+        // with the line and the raw record.
+        let mut reader = ReaderBuilder::new()
+            .trim(Trim::All)
+            .flexible(true)
+            .has_headers(config.has_headers)
+            .delimiter(config.delimiter)
+            .quote(config.quote)
+            .from_reader(reader);
+        let aliases = config.aliases.clone();
+        let headers = if config.has_headers {
+            reader.headers()?.clone()
+        } else {
+            StringRecord::new()
+        };
+        // A zero-byte input doesn't even have a header.
+        if config.has_headers && headers.is_empty() && !config.allow_empty_input {
+            bail!("Empty input");
+        }
+        let headers = if headers.is_empty() {
+            StringRecord::from(DEFAULT_COLUMNS.to_vec())
+        } else {
+            aliases.normalize_headers(&headers)
+        };
+        validate_headers(&headers, config.strict)?;
+        let type_column = column(&headers, "type").expect("validated header");
+        let amount_column = column(&headers, "amount");
+        let seq_column = column(&headers, "seq");
+
+        Ok(Self {
+            reader,
+            aliases,
+            strict: config.strict,
+            max_integer_digits: config.max_integer_digits,
+            require_decimal_point: config.require_decimal_point,
+            footer: config.footer.clone(),
+            #[cfg(feature = "unicode-digits")]
+            normalize_digits: config.normalize_digits,
+            headers,
+            type_column,
+            amount_column,
+            seq_column,
+            sequences: HashMap::new(),
+            header_pending: !config.has_headers,
+            record: StringRecord::new(),
+        })
+    }
+
+    /// Read the next row and its line, or `None` at the end of the input.
+    pub(crate) fn next_row(&mut self) -> Result<Option<(u64, Row)>> {
+        if !self.read_record()? {
+            return Ok(None);
+        }
+        let stray_header = std::mem::take(&mut self.header_pending);
+
+        #[cfg(feature = "unicode-digits")]
+        if self.normalize_digits {
+            self.record = normalize_digits(&self.record);
+        }
+
+        let line = self.line();
+        if self.is_footer() {
+            let footer = raw(&self.record);
+            if self.read_record()? {
+                bail!("Footer on line {} isn't the last row: {}", line, footer);
+            }
+            return Ok(None);
+        }
+        let amount = self.amount_column.and_then(|i| self.record.get(i));
+        let columns = self.record.len();
+        if columns > self.headers.len() || (self.strict && columns != self.headers.len()) {
+            bail!(
+                "Expected {} columns but found {} on line {}: {}",
+                self.headers.len(),
+                columns,
+                line,
+                raw(&self.record)
+            );
+        }
+        // Reject absurd amounts before they can overflow when parsed.
+        if let (Some(amount), Some(max)) = (amount, self.max_integer_digits) {
+            let digits = integer_digits(amount);
+            if digits > max {
+                return self.malformed(
+                    line,
+                    format!("Amount with {} integer digits exceeds {}", digits, max),
+                );
+            }
+        }
+        if let Some(amount) = amount {
+            if !in_decimal_range(amount) {
+                return self.malformed(line, "Amount out of range".to_string());
+            }
+        }
+        let mut record = self
+            .aliases
+            .normalize_record(&self.record, self.type_column);
+        // Serde infers the type of untyped fields, which would parse amounts via a float and
+        // lose precision, so the amount is parsed separately from its text.
+        if let (Some(i), Some(amount)) = (self.amount_column, amount) {
+            if !amount.is_empty() {
+                record = replace_field(&record, i, "0");
+            }
+        }
+        let context = || {
+            format!(
+                "Failed to parse transaction on line {}: {}",
+                line,
+                raw(&self.record)
+            )
+        };
+        let mut transaction: Transaction = match record.deserialize(Some(&self.headers)) {
+            Err(_) if stray_header => {
+                let reason = format!("Header {} in input without headers", raw(&self.record));
+                return Ok(Some((line, Row::Skipped(reason))));
+            }
+            parsed => parsed.with_context(context)?,
+        };
+        // Only amounts that are used need a decimal point, others are checked by `strict`.
+        if self.require_decimal_point && transaction.variant.has_amount() {
+            if let Some(amount) = amount.filter(|a| !a.is_empty() && !a.contains('.')) {
+                return self
+                    .malformed(line, format!("Amount {:?} without a decimal point", amount));
+            }
+        }
+        match (&mut transaction.variant, amount) {
+            (TransactionVariant::Deposit { amount }, Some(text))
+            | (TransactionVariant::Withdrawal { amount }, Some(text))
+            | (TransactionVariant::Adjustment { amount }, Some(text)) => {
+                *amount = parse_amount(text).with_context(context)?;
+            }
+            _ => {}
+        }
+        if self.strict && !transaction.variant.has_amount() {
+            if let Some(amount) = amount {
+                if !amount.is_empty() {
+                    bail!(
+                        "Unexpected amount {:?} for {:?} on line {}",
+                        amount,
+                        transaction.variant,
+                        line
+                    );
+                }
+            }
+        }
+        // Rows of a client must follow each other by their sequence numbers, if they have them.
+        let seq = self
+            .seq_column
+            .and_then(|i| self.record.get(i))
+            .filter(|seq| !seq.is_empty());
+        if let Some(seq) = seq {
+            let client = transaction.client;
+            let seq: u64 = match seq.parse() {
+                Ok(seq) => seq,
+                Err(_) => return self.malformed(line, format!("Invalid sequence {:?}", seq)),
+            };
+            match self.sequences.get(&client) {
+                Some(&last) if seq <= last => {
+                    return self.malformed(
+                        line,
+                        format!("Sequence {} of client {} isn't after {}", seq, client, last),
+                    );
+                }
+                Some(&last) if self.strict && seq != last + 1 => {
+                    bail!(
+                        "Sequence of client {} skips from {} to {} on line {}",
+                        client,
+                        last,
+                        seq,
+                        line
+                    );
+                }
+                _ => {}
+            }
+            self.sequences.insert(client, seq);
+        }
+        Ok(Some((line, Row::Transaction(transaction))))
+    }
+
+    /// Read the next non-blank record, returning false at the end of the input.
+    fn read_record(&mut self) -> Result<bool> {
+        // Lines that are blank after trimming, like stray whitespace, aren't rows.
+        loop {
+            if !self.reader.read_record(&mut self.record)? {
+                return Ok(false);
+            }
+            if !self.record.iter().all(str::is_empty) {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// If the row is a footer to skip.
+    fn is_footer(&self) -> bool {
+        match (&self.footer, self.record.get(self.type_column)) {
+            (Some(footer), Some(kind)) => kind.eq_ignore_ascii_case(footer),
+            _ => false,
+        }
+    }
+
+    /// The line of the last row read.
+    pub(crate) fn line(&self) -> u64 {
+        self.record.position().map_or(0, |pos| pos.line())
+    }
+
+    /// Fail in strict mode, otherwise skip the row.
+    fn malformed(&self, line: u64, reason: String) -> Result<Option<(u64, Row)>> {
+        if self.strict {
+            bail!("{} on line {}: {}", reason, line, raw(&self.record));
+        }
+        Ok(Some((line, Row::Skipped(reason))))
+    }
+}
+
+/// Parse an amount exactly, normalized to drop trailing zeros.
+fn parse_amount(amount: &str) -> Result<Decimal> {
+    let parsed = Decimal::from_str(amount)
+        .or_else(|_| Decimal::from_scientific(amount))
+        .map_err(|_| anyhow!("Invalid amount {:?}", amount))?;
+    Ok(parsed.normalize())
+}
+
+fn replace_field(record: &StringRecord, i: usize, value: &str) -> StringRecord {
+    let mut replaced: StringRecord = record
+        .iter()
+        .enumerate()
+        .map(|(j, field)| if j == i { value } else { field })
+        .collect();
+    replaced.set_position(record.position().cloned());
+    replaced
+}
+
+/// The digits of the integer part of an amount, without the sign and leading zeros.
+fn integer_part(amount: &str) -> &str {
+    amount
+        .trim_start_matches(['-', '+'])
+        .split('.')
+        .next()
+        .unwrap_or("")
+        .trim_start_matches('0')
+}
+
+/// The number of digits in the integer part of an amount, ignoring the sign and leading zeros.
+fn integer_digits(amount: &str) -> usize {
+    integer_part(amount).len()
+}
+
+/// If the integer part of an amount fits in a Decimal, larger amounts lose precision when parsed.
+fn in_decimal_range(amount: &str) -> bool {
+    let max = Decimal::MAX.to_string();
+    let integer = integer_part(amount);
+    integer.len() < max.len() || (integer.len() == max.len() && integer <= max.as_str())
+}
+
+/// The zero of each block of Unicode decimal digits that's converted, the following nine
+/// code points are the digits one to nine.
+#[cfg(feature = "unicode-digits")]
+const DIGIT_ZEROS: [u32; 12] = [
+    0x0660, // Arabic-Indic
+    0x06F0, // Extended Arabic-Indic
+    0x07C0, // NKo
+    0x0966, // Devanagari
+    0x09E6, // Bengali
+    0x0A66, // Gurmukhi
+    0x0AE6, // Gujarati
+    0x0E50, // Thai
+    0x0ED0, // Lao
+    0x0F20, // Tibetan
+    0x1040, // Myanmar
+    0xFF10, // Fullwidth
+];
+
+/// Replace Unicode decimal digits with their ASCII equivalent in all fields.
+#[cfg(feature = "unicode-digits")]
+fn normalize_digits(record: &StringRecord) -> StringRecord {
+    let mut normalized: StringRecord = record
+        .iter()
+        .map(|field| field.chars().map(ascii_digit).collect::<String>())
+        .collect();
+    normalized.set_position(record.position().cloned());
+    normalized
+}
+
+#[cfg(feature = "unicode-digits")]
+fn ascii_digit(c: char) -> char {
+    let code = c as u32;
+    DIGIT_ZEROS
+        .iter()
+        .find(|&&zero| (zero..zero + 10).contains(&code))
+        .map_or(c, |zero| (b'0' + (code - zero) as u8) as char)
+}
+
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+const OPTIONAL_COLUMNS: [&str; 3] = ["amount", "currency", "seq"];
+/// The columns of an input without headers, or an empty input.
+const DEFAULT_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Validate that the required columns exist and, in strict mode, that there are no unknown columns.
+fn validate_headers(headers: &StringRecord, strict: bool) -> Result<()> {
+    for required in REQUIRED_COLUMNS.iter() {
+        if column(headers, required).is_none() {
+            bail!("Missing column '{}' in header {}", required, raw(headers));
+        }
+    }
+    if strict {
+        if let Some(unknown) = headers
+            .iter()
+            .find(|header| !REQUIRED_COLUMNS.contains(header) && !OPTIONAL_COLUMNS.contains(header))
+        {
+            bail!("Unknown column '{}' in header {}", unknown, raw(headers));
+        }
+    }
+    Ok(())
+}
+
+fn column(headers: &StringRecord, name: &str) -> Option<usize> {
+    headers.iter().position(|header| header == name)
+}
+
+/// The record as it would appear in the csv input, after trimming.
+fn raw(record: &StringRecord) -> String {
+    record.iter().collect::<Vec<_>>().join(",")
+}
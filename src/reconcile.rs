@@ -0,0 +1,33 @@
+use crate::client::ClientOutput;
+
+/// A client whose state differs from the expected output.
+///
+/// `expected` or `actual` is missing if the client only exists on one side.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discrepancy {
+    pub client: u32,
+    pub expected: Option<ClientOutput>,
+    pub actual: Option<ClientOutput>,
+}
+
+impl Discrepancy {
+    /// Render the discrepancy as unified-diff like text, with the expected row prefixed by `-`
+    /// and the actual row prefixed by `+`.
+    pub fn render(&self) -> String {
+        let mut text = format!("@@ client {} @@\n", self.client);
+        if let Some(expected) = &self.expected {
+            text.push_str(&format!("-{}\n", render_row(expected)));
+        }
+        if let Some(actual) = &self.actual {
+            text.push_str(&format!("+{}\n", render_row(actual)));
+        }
+        text
+    }
+}
+
+fn render_row(row: &ClientOutput) -> String {
+    format!(
+        "{},{},{},{},{}",
+        row.client, row.available, row.held, row.total, row.locked
+    )
+}
@@ -0,0 +1,15 @@
+/// Tallies of the transactions processed by an atm.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessingReport {
+    /// Transactions that were parsed and applied.
+    pub processed: usize,
+    /// Transactions that were parsed but ignored.
+    pub skipped: usize,
+    /// Transactions ignored because the client was locked, like a deposit after
+    /// a chargeback, with the `reject_locked` option. Also counted as skipped.
+    pub rejected_due_to_lock: usize,
+    /// The client and tx ids of txs still disputed at the end of the input, sorted by ids.
+    ///
+    /// Only filled in with the `report_unresolved_disputes` option.
+    pub unresolved_disputes: Vec<(u32, u32)>,
+}
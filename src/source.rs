@@ -0,0 +1,68 @@
+use anyhow::Result;
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::atm::Transaction;
+use crate::config::Config;
+use crate::reader::{Row, TransactionReader};
+
+/// A source of transactions, like a csv file or a database cursor.
+///
+/// Any iterator over `Result<Transaction>` is a source.
+pub trait TransactionSource {
+    /// The next transaction, or `None` when the source is exhausted.
+    ///
+    /// A row that should be skipped, and counted as such, is yielded as a
+    /// `SkippedRow` error.
+    fn next_transaction(&mut self) -> Option<Result<Transaction>>;
+}
+
+impl<I> TransactionSource for I
+where
+    I: Iterator<Item = Result<Transaction>>,
+{
+    fn next_transaction(&mut self) -> Option<Result<Transaction>> {
+        self.next()
+    }
+}
+
+/// A malformed row skipped by a source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRow {
+    pub line: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for SkippedRow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Skipped line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Error for SkippedRow {}
+
+/// Transactions in a csv format.
+pub struct CsvSource<R> {
+    reader: TransactionReader<R>,
+}
+
+impl<R: io::Read> CsvSource<R> {
+    /// Read transactions from 'reader', with the header validated up front.
+    pub fn new(reader: R, config: &Config) -> Result<Self> {
+        Ok(Self {
+            reader: TransactionReader::new(reader, config)?,
+        })
+    }
+}
+
+impl<R: io::Read> TransactionSource for CsvSource<R> {
+    fn next_transaction(&mut self) -> Option<Result<Transaction>> {
+        match self.reader.next_row() {
+            Ok(Some((_, Row::Transaction(transaction)))) => Some(Ok(transaction)),
+            Ok(Some((line, Row::Skipped(reason)))) => Some(Err(SkippedRow { line, reason }.into())),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
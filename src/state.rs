@@ -0,0 +1,39 @@
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// The kind of a row in a saved state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    /// The balances of a client.
+    Client,
+    /// A deposit held by the preceding client.
+    Deposit,
+    /// A withdrawal held by the preceding client.
+    Withdrawal,
+    /// An adjustment held by the preceding client.
+    Adjustment,
+}
+
+/// A row in a saved state.
+///
+/// Client rows fill in the balances and tx rows fill in the tx fields,
+/// leaving the other columns empty.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateRow {
+    pub record: RecordKind,
+    pub client: u32,
+    pub available: Option<Decimal>,
+    pub held: Option<Decimal>,
+    pub locked: Option<bool>,
+    pub tx: Option<u32>,
+    pub amount: Option<Decimal>,
+    pub disputed: Option<bool>,
+    pub reversed: Option<bool>,
+    /// The currency of a client, missing in states saved without currencies.
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// If a tx has been charged back, missing in states saved before it was tracked.
+    #[serde(default)]
+    pub charged_back: Option<bool>,
+}
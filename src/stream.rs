@@ -0,0 +1,114 @@
+use anyhow::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::atm::{Atm, Transaction};
+
+/// A source of transactions that arrive asynchronously.
+///
+/// Mirrors the `Stream` trait of the `futures` crate without depending on it,
+/// so streams from any async runtime can be adapted to it.
+pub trait TransactionStream {
+    /// Poll for the next transaction, returning `None` when the stream is finished.
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Transaction>>;
+}
+
+impl Atm {
+    /// Process transactions as they arrive from 'stream'.
+    ///
+    /// Only the ingestion is asynchronous, each transaction is applied synchronously.
+    pub async fn process_stream<S>(&mut self, mut stream: S) -> Result<()>
+    where
+        S: TransactionStream + Unpin,
+    {
+        while let Some(transaction) = next(&mut stream).await {
+            self.execute(transaction)?;
+        }
+        Ok(())
+    }
+}
+
+fn next<S>(stream: &mut S) -> Next<'_, S> {
+    Next { stream }
+}
+
+/// A future resolving to the next transaction of a stream.
+struct Next<'a, S> {
+    stream: &'a mut S,
+}
+
+impl<S: TransactionStream + Unpin> Future for Next<'_, S> {
+    type Output = Option<Transaction>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::task::Wake;
+
+    use crate::reader::{Row, TransactionReader};
+
+    /// Yields every other poll, to exercise the pending path.
+    struct SlowStream {
+        transactions: VecDeque<Transaction>,
+        ready: bool,
+    }
+
+    impl TransactionStream for SlowStream {
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Transaction>> {
+            self.ready = !self.ready;
+            if self.ready {
+                Poll::Ready(self.transactions.pop_front())
+            } else {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Arc::new(NoopWaker).into();
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn process_stream_matches_sync_path() {
+        let path = Path::new("test_files/dispute_deposit.in");
+        let file = std::fs::File::open(path).unwrap();
+        let mut reader = TransactionReader::new(file, &Default::default()).unwrap();
+        let mut transactions = VecDeque::new();
+        while let Some((_, row)) = reader.next_row().unwrap() {
+            if let Row::Transaction(transaction) = row {
+                transactions.push_back(transaction);
+            }
+        }
+
+        let mut atm = Atm::new();
+        let stream = SlowStream {
+            transactions,
+            ready: false,
+        };
+        block_on(atm.process_stream(stream)).unwrap();
+        assert_eq!(atm.balances(), Atm::from_path(path).unwrap().balances());
+    }
+}
@@ -0,0 +1,45 @@
+use std::collections::BTreeMap;
+
+use crate::client::IgnoreReason;
+
+/// A problem with a row found when validating input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The row can't be read or applied.
+    Error(String),
+    /// The row would be ignored.
+    Warning(IgnoreReason),
+}
+
+/// The problems found when validating input, keyed by line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub diagnostics: BTreeMap<u64, Diagnostic>,
+}
+
+impl ValidationReport {
+    /// If the input can be processed without errors or ignored transactions.
+    pub fn is_clean(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// The lines with errors.
+    pub fn errors(&self) -> impl Iterator<Item = (u64, &str)> {
+        self.diagnostics
+            .iter()
+            .filter_map(|(line, diagnostic)| match diagnostic {
+                Diagnostic::Error(message) => Some((*line, message.as_str())),
+                Diagnostic::Warning(_) => None,
+            })
+    }
+
+    /// The lines with transactions that would be ignored.
+    pub fn warnings(&self) -> impl Iterator<Item = (u64, IgnoreReason)> + '_ {
+        self.diagnostics
+            .iter()
+            .filter_map(|(line, diagnostic)| match diagnostic {
+                Diagnostic::Warning(reason) => Some((*line, *reason)),
+                Diagnostic::Error(_) => None,
+            })
+    }
+}